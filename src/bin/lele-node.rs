@@ -0,0 +1,141 @@
+// An always-on node: keeps the 'hosted_slots' of one or more topics online, so a swarm
+// stays reachable when the last of its users leaves.
+//
+//   lele-node [--state-dir <dir>] [<config.toml> ...]
+//
+// Every config file is the 'ConnectConfig' of one topic, without any the config is read
+// from '$LELE_CONFIG' and the environment. Unless the config gives their paths, the identity
+// and the peer cache of a topic are kept in '<state-dir>/<topic>/': a restarted node comes
+// back with the same node id and finds its peers again. SIGINT and SIGTERM release the slots
+// and close the endpoints, the exit code is 1 when the shutdown did not finish.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use iroh_gossip::net::{Event, GossipEvent, GossipReceiver};
+use lele::iroh::{ConnectConfig, Connection, ServerFuture, User};
+use n0_future::TryStreamExt;
+use tokio::task::JoinHandle;
+
+const DEFAULT_STATE_DIR: &str = "lele-node-state";
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(15);
+
+struct Node {
+    topic: String,
+    user: User,
+    server_future: ServerFuture,
+    // Drains the user subscription, the node does not read the messages.
+    watcher: JoinHandle<()>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let (state_dir, config_paths) = parse_args(std::env::args().skip(1))?;
+    let configs = load_configs(&state_dir, &config_paths)?;
+
+    let mut nodes = Vec::with_capacity(configs.len());
+    for config in configs {
+        let topic = hex::encode(config.topic_id.as_bytes());
+        let hosted_slots = config.options.hosted_slots.clone();
+        let connection = Connection::create_from_config(config).await?;
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let node_id = user.node_id().map(|node_id| node_id.to_string()).unwrap_or_default();
+        println!("> topic {topic}: node {node_id} hosting slots {hosted_slots:?}");
+        let (_, receiver) = user_gossip_topic.split();
+        let watcher = tokio::spawn(watch_neighbours(topic.clone(), receiver));
+        nodes.push(Node { topic, user, server_future, watcher });
+    }
+
+    wait_for_signal().await?;
+    println!("> shutting down ...");
+    let shutdowns = nodes.into_iter().map(|node| async move {
+        node.watcher.abort();
+        let report = node.server_future.shutdown(node.user, SHUTDOWN_TIMEOUT).await;
+        (node.topic, report)
+    });
+    let mut clean = true;
+    for (topic, report) in futures::future::join_all(shutdowns).await {
+        println!("> topic {topic}: {report:?}");
+        clean &= report.is_clean();
+    }
+    if !clean {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut state_dir = PathBuf::from(DEFAULT_STATE_DIR);
+    let mut config_paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--state-dir" => match args.next() {
+                None => bail!("'--state-dir' needs a directory"),
+                Some(dir) => state_dir = PathBuf::from(dir),
+            },
+            "-h" | "--help" => {
+                println!("usage: lele-node [--state-dir <dir>] [<config.toml> ...]");
+                std::process::exit(0);
+            }
+            flag if flag.starts_with('-') => bail!("unknown option '{flag}'"),
+            path => config_paths.push(PathBuf::from(path)),
+        }
+    }
+    Ok((state_dir, config_paths))
+}
+
+fn load_configs(state_dir: &Path, config_paths: &[PathBuf]) -> Result<Vec<ConnectConfig>> {
+    let mut configs = match config_paths.is_empty() {
+        true => vec![ConnectConfig::load(None)?],
+        false => config_paths
+            .iter()
+            .map(|path| ConnectConfig::from_file(path).map_err(|e| anyhow!("{}: {e}", path.display())))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let mut topics = HashSet::new();
+    for config in configs.iter_mut() {
+        let topic = hex::encode(config.topic_id.as_bytes());
+        if !topics.insert(topic.clone()) {
+            bail!("topic {topic} is configured twice");
+        }
+        if config.options.hosted_slots.is_empty() {
+            tracing::warn!(%topic, "no 'hosted_slots' configured, only the bootstrap slot is hosted");
+        }
+        let topic_dir = state_dir.join(&topic);
+        config.identity_path.get_or_insert_with(|| topic_dir.join("identity.key"));
+        config.options.peer_cache.get_or_insert_with(|| topic_dir.join("peers.toml"));
+    }
+    Ok(configs)
+}
+
+async fn watch_neighbours(topic: String, mut receiver: GossipReceiver) {
+    while let Ok(Some(event)) = receiver.try_next().await {
+        match event {
+            Event::Gossip(GossipEvent::NeighborUp(node_id)) => tracing::info!(%topic, %node_id, "neighbour up"),
+            Event::Gossip(GossipEvent::NeighborDown(node_id)) => tracing::info!(%topic, %node_id, "neighbour down"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        signal = tokio::signal::ctrl_c() => signal?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use iroh::RelayUrl;
+
+use crate::{iroh::SlotSearchError, thread::TimeoutError};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Every failure of lele's public APIs, so apps can react to a specific one.
+// The '&'static str' fields and 'at' tell where the error comes from, like "instance::subscribe".
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}: the instance is empty")]
+    EmptyInstance(&'static str),
+    #[error("the endpoint uses relay {endpoint:?}, the instance expects {expected:?}")]
+    RelayMismatch {
+        endpoint: Option<RelayUrl>,
+        expected: Option<RelayUrl>,
+    },
+    #[error("{0}: no relay url found")]
+    NoRelayUrl(&'static str),
+    #[error("invalid relay url: {0}")]
+    InvalidRelayUrl(#[from] iroh::RelayUrlParseError),
+    #[error("{at}: invalid {signed} signature")]
+    Signature { at: &'static str, signed: &'static str },
+    #[error("could not decode: {0}")]
+    Decode(#[from] postcard::Error),
+    // Valid but refused, like a forked key rotation or a certificate for another device.
+    #[error("{at}: rejected, {reason}")]
+    Rejected { at: &'static str, reason: &'static str },
+    #[error("timed out")]
+    Timeout,
+    #[error(transparent)]
+    BootstrapExhausted(#[from] SlotSearchError),
+    #[error("{0}: no offline server slot to choose from")]
+    NoOfflineSlot(&'static str),
+    // A configuration value, from the builder, a file or the environment, is invalid.
+    #[error("invalid config '{field}': {reason}")]
+    Config { field: &'static str, reason: String },
+    #[error("could not parse the config file: {0}")]
+    ConfigFile(#[from] toml::de::Error),
+    #[error("could not write the peer cache: {0}")]
+    PeerCache(#[from] toml::ser::Error),
+    // The steps of a shutdown that failed or timed out.
+    #[error("shutdown did not finish: {}", .0.join(", "))]
+    Shutdown(Vec<String>),
+    #[error("{0}: lock poisoned")]
+    LockPoisoned(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error(transparent)]
+    Gossip(#[from] iroh_gossip::net::Error),
+    // Errors of iroh itself, by what failed.
+    #[error("could not bind the endpoint: {0}")]
+    Bind(anyhow::Error),
+    #[error("router failed: {0}")]
+    Router(anyhow::Error),
+    #[error("could not get or add a node address: {0}")]
+    NodeAddr(anyhow::Error),
+    #[error("invalid relay map: {0}")]
+    RelayMap(anyhow::Error),
+}
+
+impl From<TimeoutError> for Error {
+    fn from(_: TimeoutError) -> Self {
+        Error::Timeout
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for Error {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        Error::Timeout
+    }
+}
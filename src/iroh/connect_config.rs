@@ -0,0 +1,490 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use iroh::{NodeAddr, NodeId, RelayUrl, SecretKey};
+use iroh_gossip::proto::TopicId;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{Result, consts::RELAY_VEC};
+
+use super::{
+    ConnectOptions, Connection, DiscoveryConfig, RelayConfig, ServerKeys, User,
+    connect_options_builder::config_error,
+};
+
+// The environment variable with the path of the config file read by 'ConnectConfig::load'.
+pub const CONFIG_PATH_VAR: &str = "LELE_CONFIG";
+
+// Used to turn a passphrase into a seed, changing it changes every seed.
+const PASSPHRASE_CONTEXT: &str = "lele 2025-01-01 passphrase seed";
+
+// Everything needed to join a swarm, instead of the constants in 'consts.rs'.
+// It is read from a TOML file and/or from the 'LELE_*' environment variables:
+//
+//   topic = "c83e12f6..."                  # LELE_TOPIC
+//   seed = "9913c171..."                   # LELE_SEED, or
+//   passphrase = "correct horse"           # LELE_PASSPHRASE, high-entropy, see 'seed_from_passphrase'
+//   relays = ["https://relay.example./"]   # LELE_RELAYS, comma separated
+//   lan_only = false                       # LELE_LAN_ONLY
+//   search_duration_ms = 7000              # LELE_SEARCH_DURATION_MS
+//   slot_range = "0..250"                  # LELE_SLOT_RANGE
+//   known_addresses = ["<node_id>@192.168.1.2:11204", "<node_id>@https://relay.example./"]
+//                                          # LELE_KNOWN_ADDRESSES, comma separated
+//   identity_path = "lele.key"             # LELE_IDENTITY_PATH
+//   pkarr_relay = "https://dns.iroh.link/pkarr"   # LELE_PKARR_RELAY
+//   dht = false                            # LELE_DHT
+//   peer_cache = "peers.toml"              # LELE_PEER_CACHE
+//   server_keys = "seed_only"              # LELE_SERVER_KEYS, "per_topic" for separate slots per topic
+//   hosted_slots = [0, 1]                  # LELE_HOSTED_SLOTS, comma separated
+//
+// Only topic and seed (or passphrase) are required, the environment overrides the file.
+#[derive(Debug, Clone)]
+pub struct ConnectConfig {
+    pub topic_id: TopicId,
+    pub seed: [u8; 32],
+    pub relays: Vec<String>,
+    // The file keeping the secret key of the user, created on first use.
+    // Without it every connection gets a new random identity.
+    pub identity_path: Option<PathBuf>,
+    pub options: ConnectOptions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    topic: Option<String>,
+    seed: Option<String>,
+    passphrase: Option<String>,
+    relays: Option<Vec<String>>,
+    lan_only: Option<bool>,
+    search_duration_ms: Option<u64>,
+    slot_range: Option<String>,
+    known_addresses: Option<Vec<String>>,
+    identity_path: Option<PathBuf>,
+    pkarr_relay: Option<String>,
+    dht: Option<bool>,
+    peer_cache: Option<PathBuf>,
+    server_keys: Option<String>,
+    hosted_slots: Option<Vec<u64>>,
+}
+
+impl ConnectConfig {
+    pub fn from_toml_str(toml_str: &str) -> Result<Self> {
+        toml::from_str::<RawConfig>(toml_str)?.resolve()
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        RawConfig::from_file(path.as_ref())?.resolve()
+    }
+
+    pub fn from_env() -> Result<Self> {
+        RawConfig::from_vars(env_vars())?.resolve()
+    }
+
+    // The file at 'path', or at '$LELE_CONFIG' when 'path' is None, overridden by the environment.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_PATH_VAR).map(PathBuf::from));
+        let file = match path {
+            None => RawConfig::default(),
+            Some(path) => RawConfig::from_file(&path)?,
+        };
+        file.merge(RawConfig::from_vars(env_vars())?).resolve()
+    }
+
+    pub fn relays_str(&self) -> Vec<&str> {
+        self.relays.iter().map(String::as_str).collect()
+    }
+
+    // The secret key kept at 'identity_path', a new one is written there if the file is missing.
+    pub fn identity(&self) -> Result<Option<SecretKey>> {
+        let Some(path) = &self.identity_path else {
+            return Ok(None);
+        };
+        if path.exists() {
+            let content = std::fs::read_to_string(path)?;
+            let bytes: [u8; 32] = decode_hex_32(content.trim())
+                .map_err(|reason| config_error("identity_path", format!("{}: {reason}", path.display())))?;
+            return Ok(Some(SecretKey::from_bytes(&bytes)));
+        }
+        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Created with its final permissions, the key is never readable by others, and an
+        // identity written meanwhile by another process is not overwritten.
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(hex::encode(secret_key.to_bytes()).as_bytes())?;
+        tracing::info!(path = %path.display(), node_id = %secret_key.public(), "new identity written");
+        Ok(Some(secret_key))
+    }
+}
+
+impl Connection {
+    pub async fn create_from_config(config: ConnectConfig) -> Result<Self> {
+        let relays_str = config.relays_str();
+        let relay_mode = config.options.relay_config.relay_mode(&relays_str)?;
+        let user = match config.identity()? {
+            None => User::random_with_topic(config.topic_id, relay_mode, config.options.discovery.clone()).await?,
+            Some(secret_key) => User::with_secret_key(secret_key, config.topic_id, relay_mode, config.options.discovery.clone()).await?,
+        };
+        Connection::create_with_user(user, &relays_str, &config.seed, config.options.clone()).await
+    }
+}
+
+impl RawConfig {
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut raw = RawConfig::default();
+        for (key, value) in vars {
+            match key.as_str() {
+                "LELE_TOPIC" => raw.topic = Some(value),
+                "LELE_SEED" => raw.seed = Some(value),
+                "LELE_PASSPHRASE" => raw.passphrase = Some(value),
+                "LELE_RELAYS" => raw.relays = Some(split_list(&value)),
+                "LELE_LAN_ONLY" => raw.lan_only = Some(parse_var("lan_only", &value)?),
+                "LELE_SEARCH_DURATION_MS" => {
+                    raw.search_duration_ms = Some(parse_var("search_duration_ms", &value)?)
+                }
+                "LELE_SLOT_RANGE" => raw.slot_range = Some(value),
+                "LELE_KNOWN_ADDRESSES" => raw.known_addresses = Some(split_list(&value)),
+                "LELE_IDENTITY_PATH" => raw.identity_path = Some(PathBuf::from(value)),
+                "LELE_PKARR_RELAY" => raw.pkarr_relay = Some(value),
+                "LELE_DHT" => raw.dht = Some(parse_var("dht", &value)?),
+                "LELE_PEER_CACHE" => raw.peer_cache = Some(PathBuf::from(value)),
+                "LELE_SERVER_KEYS" => raw.server_keys = Some(value),
+                "LELE_HOSTED_SLOTS" => {
+                    let slots = split_list(&value).into_iter().map(|slot| parse_var("hosted_slots", &slot));
+                    raw.hosted_slots = Some(slots.collect::<Result<_>>()?);
+                }
+                CONFIG_PATH_VAR => {}
+                key if key.starts_with("LELE_") => {
+                    tracing::warn!(key, "unknown lele environment variable, ignored");
+                }
+                _ => {}
+            }
+        }
+        Ok(raw)
+    }
+
+    // The values of 'other' win.
+    fn merge(self, other: RawConfig) -> Self {
+        // A seed from the environment replaces a passphrase from the file, and the other way around.
+        let secret_given = other.seed.is_some() || other.passphrase.is_some();
+        RawConfig {
+            topic: other.topic.or(self.topic),
+            seed: if secret_given { other.seed } else { self.seed },
+            passphrase: if secret_given { other.passphrase } else { self.passphrase },
+            relays: other.relays.or(self.relays),
+            lan_only: other.lan_only.or(self.lan_only),
+            search_duration_ms: other.search_duration_ms.or(self.search_duration_ms),
+            slot_range: other.slot_range.or(self.slot_range),
+            known_addresses: other.known_addresses.or(self.known_addresses),
+            identity_path: other.identity_path.or(self.identity_path),
+            pkarr_relay: other.pkarr_relay.or(self.pkarr_relay),
+            dht: other.dht.or(self.dht),
+            peer_cache: other.peer_cache.or(self.peer_cache),
+            server_keys: other.server_keys.or(self.server_keys),
+            hosted_slots: other.hosted_slots.or(self.hosted_slots),
+        }
+    }
+
+    fn resolve(self) -> Result<ConnectConfig> {
+        let topic = self.topic.ok_or_else(|| config_error("topic", "missing"))?;
+        let topic_id = decode_hex_32(&topic)
+            .map(TopicId::from_bytes)
+            .map_err(|reason| config_error("topic", reason))?;
+        let seed = match (self.seed, self.passphrase) {
+            (Some(_), Some(_)) => return Err(config_error("seed", "give either a seed or a passphrase, not both")),
+            (None, None) => return Err(config_error("seed", "missing, give a seed or a passphrase")),
+            (Some(seed), None) => decode_hex_32(&seed).map_err(|reason| config_error("seed", reason))?,
+            (None, Some(passphrase)) => seed_from_passphrase(&passphrase)?,
+        };
+
+        let lan_only = self.lan_only.unwrap_or(false);
+        let relays = match (self.relays, lan_only) {
+            (Some(_), true) => return Err(config_error("relays", "no relay is used with lan_only")),
+            (None, true) => Vec::new(),
+            (None, false) => RELAY_VEC.iter().map(|s| s.to_string()).collect(),
+            (Some(relays), false) if relays.is_empty() => {
+                return Err(config_error("relays", "empty, use lan_only to go without relays"));
+            }
+            (Some(relays), false) => relays,
+        };
+        for relay in &relays {
+            RelayUrl::from_str(relay).map_err(|e| config_error("relays", format!("{relay}: {e}")))?;
+        }
+
+        let mut builder = ConnectOptions::builder();
+        if lan_only {
+            builder = builder.relay_config(RelayConfig::Disabled);
+        }
+        if let Some(search_duration_ms) = self.search_duration_ms {
+            builder = builder.search_duration(Duration::from_millis(search_duration_ms));
+        }
+        if let Some(slot_range) = self.slot_range {
+            let (start, end) = slot_range
+                .split_once("..")
+                .and_then(|(start, end)| Some((start.trim().parse().ok()?, end.trim().parse().ok()?)))
+                .ok_or_else(|| config_error("slot_range", format!("'{slot_range}' is not like '0..250'")))?;
+            if start >= end {
+                return Err(config_error("slot_range", format!("'{slot_range}' is empty")));
+            }
+            builder = builder.slot_range(start..end);
+        }
+        if let Some(known_addresses) = self.known_addresses {
+            builder = builder.known_addresses(parse_known_addresses(&known_addresses)?);
+        }
+        let pkarr_relay = match self.pkarr_relay {
+            None => None,
+            Some(pkarr_relay) => Some(
+                Url::parse(pkarr_relay.trim())
+                    .map_err(|e| config_error("pkarr_relay", format!("'{pkarr_relay}': {e}")))?,
+            ),
+        };
+        builder = builder.discovery(match (pkarr_relay, self.dht.unwrap_or(false)) {
+            (pkarr_relay, true) => DiscoveryConfig::Dht(pkarr_relay),
+            (Some(pkarr_relay), false) => DiscoveryConfig::PkarrRelay(pkarr_relay),
+            (None, false) => DiscoveryConfig::LocalNetwork,
+        });
+        if let Some(peer_cache) = self.peer_cache {
+            builder = builder.peer_cache(peer_cache);
+        }
+        if let Some(server_keys) = self.server_keys {
+            builder = builder.server_keys(match server_keys.trim() {
+                "per_topic" => ServerKeys::PerTopic,
+                "seed_only" => ServerKeys::SeedOnly,
+                _ => {
+                    let reason = format!("'{server_keys}' is neither 'per_topic' nor 'seed_only'");
+                    return Err(config_error("server_keys", reason));
+                }
+            });
+        }
+        if let Some(hosted_slots) = self.hosted_slots {
+            builder = builder.hosted_slots(hosted_slots);
+        }
+        Ok(ConnectConfig {
+            topic_id,
+            seed,
+            relays,
+            identity_path: self.identity_path,
+            options: builder.build()?,
+        })
+    }
+}
+
+// A fast key derivation, not a password hash: anyone who knows the topic can try guesses
+// offline at full speed. The passphrase must be high-entropy, like a few random words
+// from a large list, never a memorable password; a random 'seed' is the safer choice.
+pub fn seed_from_passphrase(passphrase: &str) -> Result<[u8; 32]> {
+    if passphrase.is_empty() {
+        return Err(config_error("passphrase", "empty"));
+    }
+    Ok(blake3::derive_key(PASSPHRASE_CONTEXT, passphrase.as_bytes()))
+}
+
+// Every address is '<node_id>', '<node_id>@<ip:port>' or '<node_id>@<relay url>',
+// the addresses of the same node are merged.
+fn parse_known_addresses(addresses: &[String]) -> Result<Vec<NodeAddr>> {
+    let mut node_addrs: BTreeMap<NodeId, NodeAddr> = BTreeMap::new();
+    for address in addresses {
+        let invalid = |reason: String| config_error("known_addresses", format!("'{address}': {reason}"));
+        let (node_id, addr) = match address.split_once('@') {
+            None => (address.as_str(), None),
+            Some((node_id, addr)) => (node_id, Some(addr)),
+        };
+        let node_id = NodeId::from_str(node_id.trim()).map_err(|e| invalid(e.to_string()))?;
+        let node_addr = node_addrs.entry(node_id).or_insert_with(|| NodeAddr::new(node_id));
+        match addr.map(str::trim) {
+            None => {}
+            Some(addr) => match SocketAddr::from_str(addr) {
+                Ok(socket_addr) => {
+                    node_addr.direct_addresses.insert(socket_addr);
+                }
+                Err(_) => {
+                    let relay_url = RelayUrl::from_str(addr)
+                        .map_err(|_| invalid("not an ip:port nor a relay url".to_string()))?;
+                    if node_addr.relay_url.as_ref().is_some_and(|url| *url != relay_url) {
+                        return Err(invalid("the node already has another relay url".to_string()));
+                    }
+                    node_addr.relay_url = Some(relay_url);
+                }
+            },
+        }
+    }
+    Ok(node_addrs.into_values().collect())
+}
+
+fn decode_hex_32(hex_str: &str) -> std::result::Result<[u8; 32], String> {
+    let bytes = hex::decode(hex_str.trim()).map_err(|e| format!("not hex: {e}"))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("{} bytes instead of 32", bytes.len()))
+}
+
+// The environment, without the variables that are not valid UTF-8: 'std::env::vars' panics on them.
+fn env_vars() -> impl Iterator<Item = (String, String)> {
+    std::env::vars_os().filter_map(|(key, value)| match (key.into_string(), value.into_string()) {
+        (Ok(key), Ok(value)) => Some((key, value)),
+        (Ok(key), Err(_)) if key.starts_with("LELE_") => {
+            tracing::warn!(key, "skipped a config variable that is not valid UTF-8");
+            None
+        }
+        _ => None,
+    })
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_var<T: FromStr>(field: &'static str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e: T::Err| config_error(field, format!("'{value}': {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, consts::{SEED, TOPIC}};
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::connect_config::tests::file_and_env -- --exact --nocapture'
+    fn file_and_env() -> Result<()> {
+        let node_id = SecretKey::generate(rand::rngs::OsRng).public();
+        let file = toml::from_str::<RawConfig>(&format!(
+            r#"
+            topic = "{TOPIC}"
+            passphrase = "correct horse"
+            relays = ["https://relay.example./"]
+            search_duration_ms = 3000
+            slot_range = "10..20"
+            known_addresses = ["{node_id}@127.0.0.1:11204", "{node_id}@https://relay.example./"]
+            pkarr_relay = "https://pkarr.example./pkarr"
+            peer_cache = "peers.toml"
+            hosted_slots = [0, 1]
+            "#
+        ))?;
+        let config = file.resolve()?;
+        assert_eq!(config.topic_id, TopicId::from_str(TOPIC).unwrap());
+        assert_eq!(config.seed, seed_from_passphrase("correct horse")?);
+        assert_eq!(config.relays_str(), vec!["https://relay.example./"]);
+        assert_eq!(config.options.search_duration, Duration::from_secs(3));
+        assert_eq!(config.options.starting_server_id, 10);
+        assert_eq!(config.options.n_server_to_search, 10);
+        assert_eq!(config.options.known_addresses.len(), 1);
+        assert_eq!(config.options.known_addresses[0].direct_addresses.len(), 1);
+        assert!(config.options.known_addresses[0].relay_url.is_some());
+        let pkarr_relay = Url::parse("https://pkarr.example./pkarr").unwrap();
+        assert_eq!(config.options.discovery, DiscoveryConfig::PkarrRelay(pkarr_relay));
+        assert_eq!(config.options.peer_cache, Some(PathBuf::from("peers.toml")));
+        assert_eq!(config.options.hosted_slots, vec![0, 1]);
+
+        // The environment wins over the file, a seed replaces the passphrase.
+        let file = toml::from_str::<RawConfig>(&format!("topic = \"{TOPIC}\"\npassphrase = \"correct horse\""))?;
+        let env = RawConfig::from_vars(vars(&[
+            ("LELE_SEED", &hex::encode(SEED)),
+            ("LELE_LAN_ONLY", "true"),
+            ("LELE_DHT", "true"),
+            ("LELE_SERVER_KEYS", "seed_only"),
+            ("LELE_HOSTED_SLOTS", "3, 4"),
+            ("HOME", "/root"),
+        ]))?;
+        let config = file.merge(env).resolve()?;
+        assert_eq!(config.seed, SEED);
+        assert!(config.relays.is_empty());
+        assert_eq!(config.options.relay_config, RelayConfig::Disabled);
+        assert_eq!(config.options.discovery, DiscoveryConfig::Dht(None));
+        assert_eq!(config.options.server_keys, ServerKeys::SeedOnly);
+        assert_eq!(config.options.hosted_slots, vec![3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::connect_config::tests::invalid_values -- --exact --nocapture'
+    fn invalid_values() -> Result<()> {
+        let field_of = |pairs: &[(&str, &str)]| match RawConfig::from_vars(vars(pairs)).and_then(RawConfig::resolve) {
+            Err(Error::Config { field, .. }) => field,
+            other => panic!("expected a config error, got {other:?}"),
+        };
+        let seed = hex::encode(SEED);
+        assert_eq!(field_of(&[("LELE_SEED", &seed)]), "topic");
+        assert_eq!(field_of(&[("LELE_TOPIC", "c83e12")]), "topic");
+        assert_eq!(field_of(&[("LELE_TOPIC", TOPIC)]), "seed");
+        let base = [("LELE_TOPIC", TOPIC), ("LELE_SEED", seed.as_str())];
+        let with = |pair: (&'static str, &'static str)| [base[0], base[1], pair];
+        assert_eq!(field_of(&with(("LELE_PASSPHRASE", "both"))), "seed");
+        assert_eq!(field_of(&with(("LELE_RELAYS", "not a url"))), "relays");
+        assert_eq!(field_of(&with(("LELE_SLOT_RANGE", "20..10"))), "slot_range");
+        assert_eq!(field_of(&with(("LELE_SLOT_RANGE", "0..20000"))), "slot_range");
+        assert_eq!(field_of(&with(("LELE_SEARCH_DURATION_MS", "0"))), "search_duration");
+        assert_eq!(field_of(&with(("LELE_SEARCH_DURATION_MS", "soon"))), "search_duration_ms");
+        assert_eq!(field_of(&with(("LELE_KNOWN_ADDRESSES", "nobody@127.0.0.1:1"))), "known_addresses");
+        assert_eq!(field_of(&with(("LELE_PKARR_RELAY", "not a url"))), "pkarr_relay");
+        assert_eq!(field_of(&with(("LELE_DHT", "maybe"))), "dht");
+        assert_eq!(field_of(&with(("LELE_SERVER_KEYS", "per_seed"))), "server_keys");
+        assert_eq!(field_of(&with(("LELE_HOSTED_SLOTS", "0,one"))), "hosted_slots");
+        assert_eq!(field_of(&with(("LELE_HOSTED_SLOTS", "0,20000"))), "hosted_slots");
+        assert!(matches!(
+            ConnectConfig::from_toml_str("topics = \"typo\""),
+            Err(Error::ConfigFile(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::connect_config::tests::identity_is_kept -- --exact --nocapture'
+    fn identity_is_kept() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("lele-identity-{}", rand::random::<u64>()));
+        let path = dir.join("lele.key");
+        let config = RawConfig::from_vars(vars(&[
+            ("LELE_TOPIC", TOPIC),
+            ("LELE_SEED", &hex::encode(SEED)),
+            ("LELE_IDENTITY_PATH", path.to_str().unwrap()),
+        ]))?
+        .resolve()?;
+        let first = config.identity()?.unwrap();
+        let second = config.identity()?.unwrap();
+        assert_eq!(first.public(), second.public());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
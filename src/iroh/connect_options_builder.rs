@@ -0,0 +1,195 @@
+use std::{collections::HashSet, ops::Range, path::PathBuf, time::Duration};
+
+use iroh::NodeAddr;
+
+use crate::{Error, Result};
+
+use super::{ConnectOptions, ConnectionEventSender, DiscoveryConfig, RelayConfig, ServerKeys, SlotStrategy};
+
+// Builds 'ConnectOptions' starting from the defaults, 'build' validates the result.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptionsBuilder {
+    options: ConnectOptions,
+}
+
+impl ConnectOptions {
+    pub fn builder() -> ConnectOptionsBuilder {
+        ConnectOptionsBuilder::default()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.search_duration.is_zero() {
+            return Err(config_error("search_duration", "must be longer than zero"));
+        }
+        if self.n_server_to_search == 0 {
+            return Err(config_error("slot_range", "must contain at least one server slot"));
+        }
+        let Some(end_id) = self.starting_server_id.checked_add(self.n_server_to_search) else {
+            return Err(config_error("slot_range", "overflows the server ids"));
+        };
+        if end_id > self.max_server_id.saturating_add(1) {
+            let reason = format!("ends at {end_id}, past the max server id {}", self.max_server_id);
+            return Err(config_error("slot_range", reason));
+        }
+        if self.merge_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(config_error("merge_interval", "must be longer than zero"));
+        }
+        if self.pex_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(config_error("pex_interval", "must be longer than zero"));
+        }
+        if self.peer_cache_interval.is_zero() {
+            return Err(config_error("peer_cache_interval", "must be longer than zero"));
+        }
+        if let Some(id) = self.hosted_slots.iter().find(|id| **id > self.max_server_id) {
+            let reason = format!("slot {id} is past the max server id {}", self.max_server_id);
+            return Err(config_error("hosted_slots", reason));
+        }
+        let mut seen = HashSet::new();
+        if let Some(id) = self.hosted_slots.iter().find(|id| !seen.insert(**id)) {
+            return Err(config_error("hosted_slots", format!("slot {id} is listed twice")));
+        }
+        if self.probe_timeout.is_zero() {
+            return Err(config_error("probe_timeout", "must be longer than zero"));
+        }
+        if self.slot_search_deadline < self.search_duration {
+            return Err(config_error("slot_search_deadline", "must not be shorter than search_duration"));
+        }
+        if let RelayConfig::Custom(relay_map) = &self.relay_config
+            && relay_map.is_empty()
+        {
+            return Err(config_error("relay_config", "the custom relay map is empty"));
+        }
+        Ok(())
+    }
+}
+
+impl ConnectOptionsBuilder {
+    pub fn search_duration(mut self, search_duration: Duration) -> Self {
+        self.options.search_duration = search_duration;
+        self
+    }
+
+    // The server slots searched first, like '0..250'.
+    pub fn slot_range(mut self, slot_range: Range<u64>) -> Self {
+        self.options.starting_server_id = slot_range.start;
+        self.options.n_server_to_search = slot_range.end.saturating_sub(slot_range.start);
+        self
+    }
+
+    pub fn known_address(mut self, node_addr: NodeAddr) -> Self {
+        self.options.known_addresses.push(node_addr);
+        self
+    }
+
+    pub fn known_addresses(mut self, node_addrs: impl IntoIterator<Item = NodeAddr>) -> Self {
+        self.options.known_addresses.extend(node_addrs);
+        self
+    }
+
+    pub fn reconnect_grace(mut self, reconnect_grace: Duration) -> Self {
+        self.options.reconnect_grace = reconnect_grace;
+        self
+    }
+
+    pub fn events(mut self, events: ConnectionEventSender) -> Self {
+        self.options.events = Some(events);
+        self
+    }
+
+    pub fn relay_config(mut self, relay_config: RelayConfig) -> Self {
+        self.options.relay_config = relay_config;
+        self
+    }
+
+    pub fn slot_strategy(mut self, slot_strategy: SlotStrategy) -> Self {
+        self.options.slot_strategy = slot_strategy;
+        self
+    }
+
+    pub fn probe_timeout(mut self, probe_timeout: Duration) -> Self {
+        self.options.probe_timeout = probe_timeout;
+        self
+    }
+
+    pub fn claim_backoff(mut self, claim_backoff: Duration) -> Self {
+        self.options.claim_backoff = claim_backoff;
+        self
+    }
+
+    pub fn claim_window(mut self, claim_window: Duration) -> Self {
+        self.options.claim_window = claim_window;
+        self
+    }
+
+    pub fn max_server_id(mut self, max_server_id: u64) -> Self {
+        self.options.max_server_id = max_server_id;
+        self
+    }
+
+    pub fn slot_search_deadline(mut self, slot_search_deadline: Duration) -> Self {
+        self.options.slot_search_deadline = slot_search_deadline;
+        self
+    }
+
+    pub fn merge_interval(mut self, merge_interval: Option<Duration>) -> Self {
+        self.options.merge_interval = merge_interval;
+        self
+    }
+
+    pub fn merge_probe_slots(mut self, merge_probe_slots: u64) -> Self {
+        self.options.merge_probe_slots = merge_probe_slots;
+        self
+    }
+
+    pub fn pex_interval(mut self, pex_interval: Option<Duration>) -> Self {
+        self.options.pex_interval = pex_interval;
+        self
+    }
+
+    pub fn discovery(mut self, discovery: DiscoveryConfig) -> Self {
+        self.options.discovery = discovery;
+        self
+    }
+
+    pub fn server_keys(mut self, server_keys: ServerKeys) -> Self {
+        self.options.server_keys = server_keys;
+        self
+    }
+
+    pub fn peer_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.peer_cache = Some(path.into());
+        self
+    }
+
+    pub fn peer_cache_interval(mut self, peer_cache_interval: Duration) -> Self {
+        self.options.peer_cache_interval = peer_cache_interval;
+        self
+    }
+
+    // The server slots kept online, each listed once, see 'ConnectOptions::hosted_slots'.
+    pub fn hosted_slots(mut self, hosted_slots: impl IntoIterator<Item = u64>) -> Self {
+        for id in hosted_slots {
+            if !self.options.hosted_slots.contains(&id) {
+                self.options.hosted_slots.push(id);
+            }
+        }
+        self
+    }
+
+    pub fn rng_seed(mut self, rng_seed: u64) -> Self {
+        self.options.rng_seed = Some(rng_seed);
+        self
+    }
+
+    pub fn build(self) -> Result<ConnectOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+pub(crate) fn config_error(field: &'static str, reason: impl Into<String>) -> Error {
+    Error::Config {
+        field,
+        reason: reason.into(),
+    }
+}
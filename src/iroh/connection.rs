@@ -1,54 +1,156 @@
 use std::{
     collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use iroh::{Endpoint, NodeAddr, NodeId, RelayUrl};
+use crate::{Error, Result};
+use iroh::{Endpoint, NodeAddr, NodeId};
 use iroh_gossip::{
     net::{Event, GossipEvent, GossipReceiver, GossipTopic},
     proto::TopicId,
 };
+use futures::future::join_all;
 use n0_future::StreamExt;
+use rand::{SeedableRng, rngs::StdRng};
+use tracing::Instrument;
 
-use super::{get_server_addresses, GossipFuture, Server, ServerFuture, User};
+use super::{
+    get_server_addr::slot_server_addr, get_server_addresses::slot_server_addresses, get_server_relay,
+    slot_claim::{CLAIMS_RNG, HELD_INTERVAL, SlotClaims, SlotReleases},
+    ConnectionEvent, ConnectionEventReceiver, ConnectionEventSender,
+    DiscoveryConfig, GossipFuture, RelayConfig, Server, ServerFuture, ServerKeys, ShutdownReport, SlotSearchError, SlotSearchLimit, SlotStrategy, User,
+    partition_merger::PartitionMerger, peer_cache::{PeerCache, keep_peer_cache}, peer_exchange::PeerExchange,
+    server_future::{LoopTasks, ServerList, lock_list}, shutdown::LeakGuard,
+};
 
 #[derive(Debug)]
 pub struct Connection {
     pub user: User,
     pub server_future: ServerFuture,
     pub user_gossip_topic: GossipTopic,
+    guard: LeakGuard,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConnectOptions {
     pub starting_server_id: u64,
-    pub debug: bool,
     pub search_duration: Duration,
     pub n_server_to_search: u64,
     pub known_addresses: Vec<NodeAddr>,
+    pub reconnect_grace: Duration,
+    pub events: Option<ConnectionEventSender>,
+    pub relay_config: RelayConfig,
+    pub slot_strategy: SlotStrategy,
+    // Claiming a slot: how long a probe waits for its server to answer, the longest
+    // random backoff and how long competing claims are waited for.
+    pub probe_timeout: Duration,
+    pub claim_backoff: Duration,
+    pub claim_window: Duration,
+    // Bounds of the server-slot search, a 'SlotSearchError' is returned past them.
+    pub max_server_id: u64,
+    pub slot_search_deadline: Duration,
+    // How often the lowest 'merge_probe_slots' server slots are probed to merge separate
+    // swarms of the topic, see 'PartitionMerger'. Opt-in, None disables it.
+    pub merge_interval: Option<Duration>,
+    pub merge_probe_slots: u64,
+    // How often the user shares a sample of its peers, see 'PeerExchange'. None disables it.
+    pub pex_interval: Option<Duration>,
+    // Used by the user and the server endpoints, see 'DiscoveryConfig'.
+    pub discovery: DiscoveryConfig,
+    // How the keys of the server slots are derived from the seed, see 'ServerKeys'.
+    pub server_keys: ServerKeys,
+    // The file of the 'PeerCache', saved every 'peer_cache_interval' and on shutdown.
+    // None disables it.
+    pub peer_cache: Option<PathBuf>,
+    pub peer_cache_interval: Duration,
+    // Server slots kept online besides the one of the bootstrap, every one found offline
+    // is claimed and hosted, see 'keep_hosted_slots'. Used by the 'lele-node' binary.
+    pub hosted_slots: Vec<u64>,
+    // Seeds the random choices of the connection, the claim backoffs and the peer samples,
+    // so a run can be replayed. None draws them from the system.
+    pub rng_seed: Option<u64>,
 }
 
 impl Default for ConnectOptions {
     fn default() -> Self {
         ConnectOptions {
             starting_server_id: 0,
-            debug: false,
             search_duration: Duration::from_secs(7),
             n_server_to_search: 250,
             known_addresses: Vec::new(),
+            reconnect_grace: Duration::from_secs(10),
+            events: None,
+            relay_config: RelayConfig::default(),
+            slot_strategy: SlotStrategy::default(),
+            probe_timeout: Duration::from_secs(1),
+            claim_backoff: Duration::from_millis(500),
+            claim_window: Duration::from_millis(500),
+            max_server_id: 10_000,
+            slot_search_deadline: Duration::from_secs(60),
+            merge_interval: None,
+            merge_probe_slots: 8,
+            pex_interval: Some(Duration::from_secs(30)),
+            discovery: DiscoveryConfig::default(),
+            server_keys: ServerKeys::default(),
+            peer_cache: None,
+            peer_cache_interval: Duration::from_secs(60),
+            hosted_slots: Vec::new(),
+            rng_seed: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    // Every connection created with these options will report its progress to the returned receiver.
+    pub fn subscribe_events(&mut self) -> ConnectionEventReceiver {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.events = Some(sender);
+        receiver
+    }
+
+    // Bootstraps without any relay, see 'RelayConfig::Disabled'.
+    pub fn lan_only() -> Self {
+        ConnectOptions {
+            relay_config: RelayConfig::Disabled,
+            ..ConnectOptions::default()
+        }
+    }
+
+    // The random generator of one loop of the connection: each 'stream' gets its own
+    // sequence from 'rng_seed'.
+    pub(crate) fn rng(&self, stream: u64) -> StdRng {
+        match self.rng_seed {
+            Some(rng_seed) => StdRng::seed_from_u64(rng_seed ^ stream.rotate_left(32)),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    pub(crate) fn emit(&self, event: ConnectionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
         }
     }
 }
 
+const RELEASE_GRACE: Duration = Duration::from_millis(500);
+const KEEP_SLOTS_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 struct ConnectionArgs {
     topic_id: TopicId,
     relay_vec: Vec<String>,
-    my_relay_url: RelayUrl,
+    // How the servers reach the user: its relay and/or its direct addresses.
+    my_addr: NodeAddr,
     seed: [u8; 32],
     server_addrs_map: HashMap<NodeId, u64>,
     last_server_id: u64,
+    claims: SlotClaims,
+    search_started: Instant,
+    servers: ServerList,
+    loops: LoopTasks,
+    discovery: DiscoveryConfig,
 }
 
 impl Connection {
@@ -62,92 +164,253 @@ impl Connection {
         seed: &[u8; 32],
         options: ConnectOptions,
     ) -> Result<Self> {
-        // if options.debug { println!(""); }
-        let relay_vec: Vec<String> = relays_str.iter().map(|s| s.to_string()).collect();
-        let user = User::random_with_topic(topic_id).await?;
-        let my_relay_url = user.relay_url().unwrap();
+        options.validate()?;
+        let relay_mode = options.relay_config.relay_mode(relays_str)?;
+        let user = User::random_with_topic(topic_id, relay_mode, options.discovery.clone()).await?;
+        Connection::create_with_user(user, relays_str, seed, options).await
+    }
+
+    // Runs the bootstrap with an already existing user, its endpoint and identity are kept.
+    #[tracing::instrument(
+        name = "connection",
+        skip_all,
+        fields(topic = ?user.topic_id(), node_id = ?user.node_id()),
+    )]
+    pub async fn create_with_user(
+        user: User,
+        relays_str: &[&str],
+        seed: &[u8; 32],
+        options: ConnectOptions,
+    ) -> Result<Self> {
+        // Options built as a struct literal skip the builder, they are checked here.
+        options.validate()?;
+        let topic_id = match user.topic_id() {
+            None => return Err(Error::EmptyInstance("connection::create_with_user")),
+            Some(topic_id) => topic_id,
+        };
+        // From here on 'seed' derives the server slots of this topic only, see 'ServerKeys'.
+        let seed = &options.server_keys.slot_seed(seed, &topic_id);
+        let peer_cache = options.peer_cache.as_ref().map(|path| PeerCache::load(path, topic_id));
+        let cached_addresses: Vec<NodeAddr> =
+            peer_cache.as_ref().map(|peer_cache| cached_peers(&options, peer_cache)).unwrap_or_default();
+        let peer_cache_interval = options.peer_cache_interval;
+        let relay_vec: Vec<String> = options.relay_config.relay_vec(relays_str);
+        let my_addr = match user.node_addr().await? {
+            None => return Err(Error::EmptyInstance("connection::create_with_user")),
+            Some(node_addr) => node_addr,
+        };
+        let (claims, releases) = SlotClaims::listen(&user, seed, options.rng(CLAIMS_RNG))?;
         let mut args = ConnectionArgs {
             topic_id,
             relay_vec,
             seed: *seed,
             server_addrs_map: HashMap::new(),
-            my_relay_url,
+            my_addr,
             last_server_id: 0,
+            claims,
+            search_started: Instant::now(),
+            servers: ServerList::default(),
+            loops: LoopTasks::default(),
+            discovery: options.discovery.clone(),
         };
+        let (servers, loops) = (args.servers.clone(), args.loops.clone());
+        let adopter_args = args.clone();
+        let adopter_options = options.clone();
+        let (merger_args, merger_options) = (args.clone(), options.clone());
+        let (keeper_args, keeper_options) = (args.clone(), options.clone());
+        let (resolver_args, resolver_options) = (args.clone(), options.clone());
+        let pex_options = options.clone();
 
         let mut user_handle: GossipFuture =
-            create_user_join_handle(&user, &mut args, &options).await?;
-        if options.debug {
-            println!("> racing subscribing and timeout ...")
-        };
-        let connection = tokio::select! {
+            create_user_join_handle(&user, &mut args, &options, cached_addresses).await?;
+        tracing::debug!("racing subscribing and timeout ...");
+        let mut connection = tokio::select! {
             finished_handle = &mut user_handle => {
-                if options.debug { println!("> found other peer/s!") };
                 let user_gossip_topic = finished_handle??;
-                let starting_server_id: u64 = get_lowest_offline_server_id(&user, &mut args, &options)?;
-                if options.debug { println!("> starting server Id({starting_server_id}) ...") };
+                tracing::info!("found other peer/s!");
+                options.emit(ConnectionEvent::PeerFound);
+                options.emit(ConnectionEvent::UserConnected);
+                let starting_server_id: u64 = choose_server_id(&user, &mut args, &options, None)?;
+                tracing::info!(server_id = starting_server_id, "starting server ...");
+                options.emit(ConnectionEvent::BecomingServer { server_id: starting_server_id });
                 let user_clone = user.clone();
                 let endpoint_clone = user.endpoint().unwrap().clone();
+                let loops_clone = loops.clone();
                 let server_future_handle = tokio::spawn ( async move {
-                    let (server, server_handle) = start_your_own_server(&user_clone, &mut args, &options).await?;
+                    let (server, server_handle) = start_your_own_server(&user_clone, &mut args, &options, starting_server_id).await?;
                     let server_gossip_topic = server_handle.await??;
-                    if options.debug { println!("> server connected!") };
+                    let server_id = server.id().unwrap();
+                    tracing::info!(server_id, "server connected!");
+                    options.emit(ConnectionEvent::ServerConnected { server_id });
                     let (_, receiver) = server_gossip_topic.split();
-                    tokio::spawn(async move { server_loop(receiver, endpoint_clone, options.debug).await });
-                    anyhow::Ok(server)
-                });
-                let server_future = ServerFuture{handle: server_future_handle};
-                Connection {user, server_future, user_gossip_topic}
+                    let span = tracing::info_span!("server_loop", server_id, node_id = ?server.node_id());
+                    let server_loop = tokio::spawn(async move { server_loop(receiver, endpoint_clone, options).await }.instrument(span));
+                    lock_list(&loops_clone, "connection::create_with_user")?.push(server_loop);
+                    Ok(server)
+                }.in_current_span());
+                let server_future = ServerFuture::with_parts(server_future_handle, servers, loops);
+                let guard = LeakGuard::new(user.endpoint());
+                Connection {user, server_future, user_gossip_topic, guard}
             }
             _ = tokio::time::sleep(options.search_duration) => {
-                if options.debug { println!("> no other peer is found ;;") };
-                let server_id: u64 = rand::random::<u64>() % options.n_server_to_search;
-                if options.debug { println!("> server_id Id({})", server_id) };
+                tracing::info!("no other peer is found ;;");
+                options.emit(ConnectionEvent::NoPeerFound);
+                let searched_ids: Vec<u64> = (options.starting_server_id..options.starting_server_id + options.n_server_to_search)
+                    .filter(|id| !options.hosted_slots.contains(id))
+                    .collect();
+                let server_id: u64 = match searched_ids.is_empty() {
+                    // Every searched slot is left to 'keep_hosted_slots'.
+                    true => choose_server_id(&user, &mut args, &options, None)?,
+                    false => options
+                        .slot_strategy
+                        .choose_alone(user.node_id().unwrap(), &searched_ids)
+                        .ok_or(Error::NoOfflineSlot("connection::create_with_user"))?,
+                };
+                let server_id: u64 = claim_server_slot(&user, &mut args, &options, server_id).await?;
+                tracing::info!(server_id, "starting server ...");
+                options.emit(ConnectionEvent::BecomingServer { server_id });
                 let (server, server_handle) = get_user_and_server_handle(
-                    server_id, user.node_id().unwrap(), &args, &options
+                    server_id, &args
                 ).await?;
-                if options.debug { println!("> server started") };
+                tracing::debug!(server_id, "server started");
                 let user_gossip_topic = user_handle.await??;
                 let endpoint_clone = user.endpoint().unwrap().clone();
-                if options.debug { println!("> user connected!") };
+                tracing::info!("user connected!");
+                options.emit(ConnectionEvent::UserConnected);
+                let loops_clone = loops.clone();
                 let server_future_handle = tokio::spawn ( async move {
                     let server_gossip_topic = server_handle.await??;
-                    if options.debug { println!("> server connected!") };
+                    tracing::info!(server_id, "server connected!");
+                    options.emit(ConnectionEvent::ServerConnected { server_id });
                     let (_, receiver) = server_gossip_topic.split();
-                    tokio::spawn(async move { server_loop(receiver, endpoint_clone, options.debug).await });
-                    anyhow::Ok(server)
-                });
-                let server_future = ServerFuture{handle: server_future_handle};
-                Connection {user, server_future, user_gossip_topic}
+                    let span = tracing::info_span!("server_loop", server_id, node_id = ?server.node_id());
+                    let server_loop = tokio::spawn(async move { server_loop(receiver, endpoint_clone, options).await }.instrument(span));
+                    lock_list(&loops_clone, "connection::create_with_user")?.push(server_loop);
+                    Ok(server)
+                }.in_current_span());
+                let server_future = ServerFuture::with_parts(server_future_handle, servers, loops);
+                let guard = LeakGuard::new(user.endpoint());
+                Connection {user, server_future, user_gossip_topic, guard}
             }
         };
+        let endpoint_clone = connection.user.endpoint().unwrap().clone();
+        let adopter = tokio::spawn(
+            adopt_released_slots(adopter_args, adopter_options, releases, endpoint_clone).in_current_span()
+        );
+        connection.server_future.adopter = Some(adopter);
+        let resolver = resolve_double_holders(resolver_args, resolver_options);
+        let resolver = tokio::spawn(resolver.in_current_span());
+        lock_list(&connection.server_future.loops, "connection::create_with_user")?.push(resolver);
+        if !keeper_options.hosted_slots.is_empty() {
+            let endpoint_clone = connection.user.endpoint().unwrap().clone();
+            let keeper = keep_hosted_slots(keeper_args, keeper_options, endpoint_clone);
+            let keeper = tokio::spawn(keeper.in_current_span());
+            lock_list(&connection.server_future.loops, "connection::create_with_user")?.push(keeper);
+        }
+        if let Some(interval) = pex_options.pex_interval {
+            let peer_exchange = PeerExchange { user: connection.user.clone(), options: pex_options };
+            let peer_exchange = tokio::spawn(peer_exchange.run(interval).in_current_span());
+            lock_list(&connection.server_future.loops, "connection::create_with_user")?.push(peer_exchange);
+        }
+        let peer_cache = peer_cache.map(|peer_cache| Arc::new(Mutex::new(peer_cache)));
+        if let Some(interval) = merger_options.merge_interval {
+            let merger = PartitionMerger {
+                user: connection.user.clone(),
+                relay_vec: merger_args.relay_vec,
+                seed: merger_args.seed,
+                servers: merger_args.servers,
+                peer_cache: peer_cache.clone(),
+                options: merger_options,
+            };
+            let merger = tokio::spawn(merger.run(interval).in_current_span());
+            lock_list(&connection.server_future.loops, "connection::create_with_user")?.push(merger);
+        }
+        if let Some(peer_cache) = peer_cache {
+            let saver = keep_peer_cache(
+                peer_cache.clone(),
+                connection.user.clone(),
+                connection.server_future.servers.clone(),
+                peer_cache_interval,
+            );
+            let saver = tokio::spawn(saver.in_current_span());
+            lock_list(&connection.server_future.loops, "connection::create_with_user")?.push(saver);
+            connection.server_future.peer_cache = Some(peer_cache);
+        }
         Ok(connection)
     }
 }
 
+impl Connection {
+    // Releases the hosted slots, stops the loops, leaves the gossip topic, then closes
+    // the server endpoints and, once they are closed, the user endpoint. Whatever did not finish
+    // before 'timeout' is in the report.
+    pub async fn shutdown(self, timeout: Duration) -> ShutdownReport {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let (user, mut server_future, user_gossip_topic) = self.into_parts();
+        let mut report = ShutdownReport::default();
+        server_future.save_peer_cache(&user, &mut report);
+        server_future.release_slots(deadline, &mut report).await;
+        server_future.stop_loops(deadline, &mut report).await;
+        drop(user_gossip_topic);
+        server_future.close_with_user(user, deadline, &mut report).await;
+        if !report.is_clean() {
+            tracing::warn!(unfinished = ?report.unfinished, "connection shutdown did not finish");
+        }
+        report
+    }
+
+    // The caller closes the parts from now on, see 'ServerFuture::shutdown'.
+    pub fn into_parts(self) -> (User, ServerFuture, GossipTopic) {
+        let Connection { user, server_future, user_gossip_topic, mut guard } = self;
+        guard.disarm();
+        (user, server_future, user_gossip_topic)
+    }
+}
+
+// The cached peers are searched with the known addresses, the ones given first. They are
+// kept out of 'options.known_addresses': the 'PartitionMerger' probes those on every tick.
+fn cached_peers(options: &ConnectOptions, peer_cache: &PeerCache) -> Vec<NodeAddr> {
+    let cached: Vec<NodeAddr> = peer_cache
+        .node_addrs()
+        .into_iter()
+        .filter(|node_addr| !options.known_addresses.iter().any(|known| known.node_id == node_addr.node_id))
+        .collect();
+    tracing::debug!(n_cached = cached.len(), "cached peers searched with the known addresses");
+    cached
+}
+
 #[rustfmt::skip]
-async fn create_user_join_handle(user: &User, args: &mut ConnectionArgs, options: &ConnectOptions) -> Result<GossipFuture> {
-    let n_known = options.known_addresses.len() as u64;
-    if options.debug { println!("> known_addresses.len(): {:?}", n_known) };
+async fn create_user_join_handle(
+    user: &User,
+    args: &mut ConnectionArgs,
+    options: &ConnectOptions,
+    cached_addresses: Vec<NodeAddr>,
+) -> Result<GossipFuture> {
+    let known_addresses: Vec<NodeAddr> = options.known_addresses.iter().cloned().chain(cached_addresses).collect();
+    let n_known = known_addresses.len() as u64;
+    tracing::debug!(n_known, "known_addresses.len()");
     let addrs_to_search = match options.n_server_to_search < n_known {
         true => {
-            if options.debug { println!("> searching only known_addresses ... ") };
-            options.known_addresses.clone()
+            tracing::debug!("searching only known_addresses ...");
+            options.emit(ConnectionEvent::SearchingServers { server_ids: Vec::new(), n_known_addresses: n_known as usize });
+            known_addresses
         },
         false => {
             let end_id = options.starting_server_id + options.n_server_to_search - n_known;
+            let end_id = end_id.min(options.max_server_id.saturating_add(1));
             let id_vec: Vec<u64> = (options.starting_server_id..end_id).collect();
-            if options.debug { println!("> search server ids:\n{:#?}", id_vec) };
+            tracing::debug!(start_id = options.starting_server_id, end_id, "search server ids");
+            options.emit(ConnectionEvent::SearchingServers { server_ids: id_vec.clone(), n_known_addresses: n_known as usize });
             args.last_server_id = end_id;
-            let mut server_addrs = get_server_addresses(&id_vec, &args.relay_vec, &args.seed)?;
+            let mut server_addrs = slot_server_addresses(&id_vec, &args.relay_vec, &args.seed)?;
             for (i, addr) in id_vec.iter().zip(server_addrs.clone()) {
                 args.server_addrs_map.insert(addr.node_id, *i);
             }
-            server_addrs.extend(options.known_addresses.clone());
+            server_addrs.extend(known_addresses);
             server_addrs
         },
     };
-    if options.debug { println!("> trying to connect to:\n{:#?}", addrs_to_search) };
+    tracing::trace!(?addrs_to_search, "trying to connect to");
     let user_handle: GossipFuture = user.connect_to_servers(addrs_to_search).await?;
     Ok(user_handle)
 }
@@ -157,13 +420,18 @@ fn add_other_servers_to_user(
     args: &mut ConnectionArgs,
     options: &ConnectOptions,
 ) -> Result<()> {
-    let end_id = args.last_server_id + options.n_server_to_search;
+    if args.last_server_id > options.max_server_id {
+        let limit = SlotSearchLimit::MaxServerId(options.max_server_id);
+        return Err(slot_search_error(user, args, limit).into());
+    }
+    let end_id = args
+        .last_server_id
+        .saturating_add(options.n_server_to_search)
+        .min(options.max_server_id.saturating_add(1));
     let id_vec: Vec<u64> = (args.last_server_id..end_id).collect();
-    if options.debug {
-        println!("> adding server ids:\n> {:?}", id_vec)
-    };
+    tracing::debug!(start_id = args.last_server_id, end_id, "adding server ids");
     args.last_server_id = end_id;
-    let server_addrs = get_server_addresses(&id_vec, &args.relay_vec, &args.seed)?;
+    let server_addrs = slot_server_addresses(&id_vec, &args.relay_vec, &args.seed)?;
     for (i, addr) in id_vec.iter().zip(server_addrs) {
         args.server_addrs_map.insert(addr.node_id, *i);
         user.add_node_addr(addr.clone())?;
@@ -171,46 +439,90 @@ fn add_other_servers_to_user(
     Ok(())
 }
 
-fn get_lowest_offline_server_id(
+// A server slot is offline when the user has no connection to its node.
+// Without relays the servers are never added to the endpoint, so only the online
+// peers are looked at, not the ones the endpoint knows about.
+// The hosted slots are never offered, 'keep_hosted_slots' takes them.
+fn get_offline_server_ids(
     user: &User,
     args: &mut ConnectionArgs,
     options: &ConnectOptions,
-) -> Result<u64> {
-    let offline_peers = user.offline_peers()?;
-    if offline_peers.is_empty() {
-        if options.debug {
-            println!("> get_lowest_offline_server_id: no offline peers found.");
+) -> Result<Vec<u64>> {
+    loop {
+        let online_peers = user.online_peers()?;
+        let mut ids: Vec<u64> = Vec::new();
+        for (node_id, id) in &args.server_addrs_map {
+            if online_peers.contains_key(node_id) || options.hosted_slots.contains(id) || args.claims.is_taken(*id)? {
+                continue;
+            }
+            ids.push(*id);
         }
-        add_other_servers_to_user(user, args, options)?;
-        return get_lowest_offline_server_id(user, args, options);
-    }
-    let id = offline_peers
-        .iter()
-        .filter_map(|p| args.server_addrs_map.get(p))
-        .min();
-    if id.is_none() {
-        if options.debug {
-            println!("> get_lowest_offline_server_id: id.is_none().");
+        if !ids.is_empty() {
+            ids.sort_unstable();
+            return Ok(ids);
         }
+        tracing::trace!("get_offline_server_ids: every searched server is online.");
+        check_slot_search_deadline(user, args, options)?;
         add_other_servers_to_user(user, args, options)?;
-        return get_lowest_offline_server_id(user, args, options);
     }
-    Ok(*id.unwrap())
+}
+
+fn check_slot_search_deadline(user: &User, args: &ConnectionArgs, options: &ConnectOptions) -> Result<()> {
+    if args.search_started.elapsed() <= options.slot_search_deadline {
+        return Ok(());
+    }
+    let limit = SlotSearchLimit::Deadline(options.slot_search_deadline);
+    Err(slot_search_error(user, args, limit).into())
+}
+
+fn slot_search_error(user: &User, args: &ConnectionArgs, limit: SlotSearchLimit) -> SlotSearchError {
+    let online_peers = user.online_peers().unwrap_or_default();
+    let online_slots = args.server_addrs_map.keys().filter(|node_id| online_peers.contains_key(*node_id)).count();
+    let taken_slots = args.server_addrs_map.values().filter(|id| args.claims.is_taken(**id).unwrap_or_default()).count();
+    let error = SlotSearchError {
+        limit,
+        searched_slots: args.server_addrs_map.len() as u64,
+        online_slots,
+        taken_slots,
+        elapsed: args.search_started.elapsed(),
+    };
+    tracing::warn!("{error}");
+    error
+}
+
+fn choose_server_id(
+    user: &User,
+    args: &mut ConnectionArgs,
+    options: &ConnectOptions,
+    previous: Option<u64>,
+) -> Result<u64> {
+    let offline_ids = get_offline_server_ids(user, args, options)?;
+    options
+        .slot_strategy
+        .choose(args.my_addr.node_id, &offline_ids, previous)
+        .ok_or(Error::NoOfflineSlot("connection::choose_server_id"))
 }
 
 async fn start_your_own_server(
     user: &User,
     args: &mut ConnectionArgs,
     options: &ConnectOptions,
+    server_id: u64,
 ) -> Result<(Server, GossipFuture)> {
-    let server_id = get_lowest_offline_server_id(user, args, options)?;
+    let claimed_id = claim_server_slot(user, args, options, server_id).await?;
+    if claimed_id != server_id {
+        options.emit(ConnectionEvent::ServerSlotReplaced { old_id: server_id, new_id: claimed_id });
+    }
+    let server_id = claimed_id;
     let (mut server, mut server_handle) =
-        get_user_and_server_handle(server_id, user.node_id().unwrap(), args, options).await?;
+        get_user_and_server_handle(server_id, args).await?;
+    // Every started server begins a new slot search, the deadline bounds each of them.
+    args.search_started = Instant::now();
     let mut last_server_id = server_id;
     let mut timer = Instant::now();
     tokio::time::sleep(Duration::from_millis(100)).await;
     while !server_handle.is_finished() {
-        let server_id = get_lowest_offline_server_id(user, args, options)?;
+        let server_id = choose_server_id(user, args, options, Some(last_server_id))?;
         match (
             last_server_id == server_id,
             timer.elapsed() <= options.search_duration,
@@ -220,72 +532,255 @@ async fn start_your_own_server(
                 continue;
             }
             _ => {
-                if options.debug {
-                    println!("> last Id({}) is no longer valid.", last_server_id)
-                }
-                if options.debug {
-                    println!("> replacing it with new Id({}).", server_id)
+                tracing::info!(old_id = last_server_id, new_id = server_id, "server slot is no longer valid, replacing it");
+                options.emit(ConnectionEvent::ServerSlotReplaced {
+                    old_id: last_server_id,
+                    new_id: server_id,
+                });
+                // The old server would keep holding its slot, even when it is the same one again.
+                server_handle.abort();
+                lock_list(&args.servers, "connection::start_your_own_server")?
+                    .retain(|held| held.node_id() != server.node_id());
+                if let Err(e) = server.close().await {
+                    tracing::warn!(server_id = last_server_id, "could not close the replaced server '{e}'");
                 }
-                // server.close().await?;
-                // server_handle.abort();
+                args.search_started = Instant::now();
+                let server_id = claim_server_slot(user, args, options, server_id).await?;
                 (server, server_handle) =
-                    get_user_and_server_handle(server_id, user.node_id().unwrap(), args, options)
-                        .await?;
+                    get_user_and_server_handle(server_id, args).await?;
                 server_handle = tokio::spawn( async move {
                     let x = server_handle.await??;
                     Ok(x)
                 });
                 last_server_id = server_id;
                 timer = Instant::now();
-                // if options.debug { println!("> user.offline_peers: {:?}", user.offline_peers())}
-                // if options.debug { println!("> user.online_peers: {:?}", user.online_peers())}
             }
         };
     }
     Ok((server, server_handle))
 }
 
+// Claims 'server_id', or the next slot chosen by the strategy when it is taken.
+async fn claim_server_slot(
+    user: &User,
+    args: &mut ConnectionArgs,
+    options: &ConnectOptions,
+    server_id: u64,
+) -> Result<u64> {
+    let mut server_id = server_id;
+    loop {
+        check_slot_search_deadline(user, args, options)?;
+        let relay_url = get_server_relay(server_id, &args.relay_vec)?;
+        let server_addr = slot_server_addr(server_id, relay_url, &args.seed);
+        if args.claims.claim(server_id, server_addr, options).await? {
+            tracing::debug!(server_id, "slot claimed");
+            return Ok(server_id);
+        }
+        server_id = choose_server_id(user, args, options, None)?;
+    }
+}
+
+// Takes over the slots released by leaving neighbours, so the deterministic bootstrap
+// addresses stay reachable. Several neighbours may try, the claim leaves one holder.
+async fn adopt_released_slots(
+    args: ConnectionArgs,
+    options: ConnectOptions,
+    mut releases: SlotReleases,
+    endpoint_clone: Endpoint,
+) -> Result<()> {
+    while let Some(server_id) = releases.recv().await {
+        options.emit(ConnectionEvent::SlotReleased { server_id });
+        if options.hosted_slots.contains(&server_id) {
+            continue;
+        }
+        // The releasing server is still up for a moment.
+        tokio::time::sleep(RELEASE_GRACE).await;
+        let relay_url = get_server_relay(server_id, &args.relay_vec)?;
+        let server_addr = slot_server_addr(server_id, relay_url, &args.seed);
+        if !args.claims.claim(server_id, server_addr, &options).await? {
+            continue;
+        }
+        tracing::info!(server_id, "adopting released slot ...");
+        host_slot(server_id, &args, &options, &endpoint_clone).await?;
+        options.emit(ConnectionEvent::SlotAdopted { server_id });
+    }
+    Ok(())
+}
+
+// Keeps every slot of 'options.hosted_slots' online. A slot held by someone else is left
+// to them, and claimed again once it goes offline.
+async fn keep_hosted_slots(args: ConnectionArgs, options: ConnectOptions, endpoint_clone: Endpoint) -> Result<()> {
+    let mut ticker = tokio::time::interval(KEEP_SLOTS_INTERVAL);
+    // A round of claims may take longer than the interval.
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let missing: Vec<u64> = {
+            let mut servers = match lock_list(&args.servers, "connection::keep_hosted_slots") {
+                Ok(servers) => servers,
+                Err(e) => {
+                    tracing::warn!("could not keep the hosted slots '{e}'");
+                    continue;
+                }
+            };
+            // A closed endpoint no longer hosts its slot, it is claimed again below.
+            servers.retain(|server| server.endpoint().is_some_and(|endpoint| !endpoint.is_closed()));
+            let hosted: Vec<u64> = servers.iter().filter_map(Server::id).collect();
+            options.hosted_slots.iter().copied().filter(|id| !hosted.contains(id)).collect()
+        };
+        // Claimed together, every claim waits for the probes and the other claimants.
+        let mut claims = Vec::new();
+        for server_id in missing {
+            let relay_url = match get_server_relay(server_id, &args.relay_vec) {
+                Ok(relay_url) => relay_url,
+                Err(e) => {
+                    tracing::warn!(server_id, "could not keep the hosted slot '{e}'");
+                    continue;
+                }
+            };
+            let server_addr = slot_server_addr(server_id, relay_url, &args.seed);
+            let (slot_claims, options) = (&args.claims, &options);
+            claims.push(async move { (server_id, slot_claims.claim(server_id, server_addr, options).await) });
+        }
+        for (server_id, claimed) in join_all(claims).await {
+            match claimed {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    tracing::warn!(server_id, "could not claim the hosted slot '{e}'");
+                    continue;
+                }
+            }
+            tracing::info!(server_id, "hosting slot ...");
+            options.emit(ConnectionEvent::BecomingServer { server_id });
+            if let Err(e) = host_slot(server_id, &args, &options, &endpoint_clone).await {
+                tracing::warn!(server_id, "could not host the slot '{e}'");
+            }
+        }
+    }
+}
+
+// Users bootstrapping alone at the same time may bind the same slot: they do not hear
+// each other's claims. The holder with the higher NodeId closes its server, like the loser
+// of a claim, once it learns about the other one: from the slot announcements when the
+// swarms are merged, or from the probes of the slot through its relay, which run from the
+// first tick on, right after binding. A lower holder reaching a higher one joins its user,
+// so the higher one hears our announcements. No release is sent, the other holder keeps
+// the slot and our user joins it.
+async fn resolve_double_holders(args: ConnectionArgs, options: ConnectOptions) -> Result<()> {
+    let mut ticker = tokio::time::interval(HELD_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let servers: Vec<Server> = lock_list(&args.servers, "connection::resolve_double_holders")?.clone();
+        for server in servers {
+            let Some(server_id) = server.id() else {
+                continue;
+            };
+            let relay_url = get_server_relay(server_id, &args.relay_vec)?;
+            let server_addr = slot_server_addr(server_id, relay_url, &args.seed);
+            let mut yields = args.claims.should_yield(server_id, server_addr.clone(), &options).await?;
+            if !yields && let Some(holder) = args.claims.other_holder(&server_addr, &options).await? {
+                yields = holder.node_id < args.my_addr.node_id;
+                if !yields {
+                    tracing::debug!(server_id, holder = %holder.node_id, "slot held by a higher user too, joining it");
+                    if let Err(e) = args.claims.join_peer(holder).await {
+                        tracing::warn!(server_id, "could not join the other holder: {e}");
+                    }
+                }
+            }
+            if !yields {
+                if let Err(e) = server.announce_held(args.my_addr.node_id).await {
+                    tracing::debug!(server_id, "could not announce the held slot: {e}");
+                }
+                continue;
+            }
+            tracing::info!(server_id, "slot held by another user, closing its server");
+            lock_list(&args.servers, "connection::resolve_double_holders")?.retain(|held| held.id() != Some(server_id));
+            options.emit(ConnectionEvent::SlotYielded { server_id });
+            if let Err(e) = server.close().await {
+                tracing::warn!(server_id, "could not close the yielded server: {e}");
+            }
+            if let Err(e) = args.claims.join_peer(server_addr).await {
+                tracing::warn!(server_id, "could not join the holder of the yielded slot: {e}");
+            }
+        }
+    }
+}
+
+// Starts the server of a claimed slot, its server loop is stopped with the other loops.
+async fn host_slot(server_id: u64, args: &ConnectionArgs, options: &ConnectOptions, endpoint_clone: &Endpoint) -> Result<()> {
+    let (server, server_handle) = get_user_and_server_handle(server_id, args).await?;
+    let options = options.clone();
+    let endpoint_clone = endpoint_clone.clone();
+    let span = tracing::info_span!("server_loop", server_id, node_id = ?server.node_id());
+    let server_loop = tokio::spawn(async move {
+        let server_gossip_topic = server_handle.await??;
+        options.emit(ConnectionEvent::ServerConnected { server_id });
+        let (_, receiver) = server_gossip_topic.split();
+        server_loop(receiver, endpoint_clone, options).await
+    }.instrument(span));
+    lock_list(&args.loops, "connection::host_slot")?.push(server_loop);
+    Ok(())
+}
+
 async fn get_user_and_server_handle(
     server_id: u64,
-    user_node_id: NodeId,
     args: &ConnectionArgs,
-    options: &ConnectOptions,
 ) -> Result<(Server, GossipFuture)> {
-    let server = Server::create(
+    let server_relay_url = get_server_relay(server_id, &args.relay_vec)?;
+    let server = Server::create_for_slot(
         server_id,
         args.topic_id,
-        args.my_relay_url.clone(),
+        server_relay_url,
         &args.seed,
+        args.discovery.clone(),
+        Some(args.my_addr.clone()),
     ).await?;
-    let debug = options.debug;
+    lock_list(&args.servers, "connection::get_user_and_server_handle")?.push(server.clone());
     let server_clone = server.clone();
-    let node_addr = NodeAddr::new(user_node_id).with_relay_url(args.my_relay_url.clone());
-    server_clone.add_node_addr(node_addr)?;
+    let user_node_id = args.my_addr.node_id;
+    if !args.my_addr.is_empty() {
+        server_clone.add_node_addr(args.my_addr.clone())?;
+    }
     let node_ids = vec![user_node_id];
+    let span = tracing::debug_span!("server", server_id, node_id = ?server.node_id());
     let server_handle: GossipFuture = tokio::spawn(async move {
         let server_gtopic = server_clone.subscribe_and_join(node_ids).await?;
-        if debug {
-            println!("\n> server_handle: connected!");
-        }
-        anyhow::Ok(server_gtopic)
-    });
+        tracing::debug!("server_handle: connected!");
+        Ok(server_gtopic)
+    }.instrument(span));
     Ok((server, server_handle))
 }
 
 #[rustfmt::skip]
-async fn server_loop(mut receiver: GossipReceiver, endpoint_clone: Endpoint, debug: bool) -> Result<()> {
+async fn server_loop(mut receiver: GossipReceiver, endpoint_clone: Endpoint, options: ConnectOptions) -> Result<()> {
     while let Some(event) = receiver.try_next().await? {
-        if let Event::Gossip(GossipEvent::NeighborUp(node_id)) = event {
-            let relay_url = endpoint_clone.
-                node_addr().
-                await?.
-                relay_url.
-                unwrap();
-            let node_addr = NodeAddr::new(node_id).with_relay_url(relay_url);
-            match endpoint_clone.add_node_addr(node_addr)  {
-                Ok(_) => if debug { println!("> server: new neighbour {node_id}"); },
-                Err(e) => if debug { println!("> server: neighbour {node_id} gave error '{e}'"); },
-            };
+        match event {
+            Event::Gossip(GossipEvent::NeighborUp(node_id)) => {
+                options.emit(ConnectionEvent::ServerNeighborUp { node_id });
+                // The neighbour may be homed on another relay than the server, or on none.
+                let node_addr = match endpoint_clone.remote_info(node_id) {
+                    Some(info) => NodeAddr::from_parts(
+                        node_id,
+                        info.relay_url.map(|info| info.relay_url),
+                        info.addrs.iter().map(|addr| addr.addr),
+                    ),
+                    None => NodeAddr::new(node_id),
+                };
+                if node_addr.is_empty() {
+                    tracing::debug!(%node_id, "new neighbour, no address known");
+                    continue;
+                }
+                match endpoint_clone.add_node_addr(node_addr)  {
+                    Ok(_) => tracing::debug!(%node_id, "new neighbour"),
+                    Err(e) => tracing::warn!(%node_id, "neighbour gave error '{e}'"),
+                };
+            }
+            Event::Gossip(GossipEvent::NeighborDown(node_id)) => {
+                options.emit(ConnectionEvent::ServerNeighborDown { node_id });
+                tracing::debug!(%node_id, "neighbour left");
+            }
+            _ => {}
         }
     }
     Ok(())
@@ -294,20 +789,22 @@ async fn server_loop(mut receiver: GossipReceiver, endpoint_clone: Endpoint, deb
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::consts::{RELAY_VEC, SEED, TOPIC};
+    use anyhow::Result;
+    use crate::{
+        consts::{RELAY_VEC, SEED, TOPIC},
+        tests::local_relay::{LocalRelay, shutdown_all},
+    };
     use std::{str::FromStr, time::Instant};
 
     #[tokio::test]
     // run test by using: 'cargo test iroh::connection::tests::connection -- --exact --nocapture'
     async fn connection() -> Result<()> {
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
         let start = Instant::now();
-        let options = ConnectOptions {
-            debug: true,
-            ..Default::default()
-        };
+        let relay = LocalRelay::spawn().await?;
+        let options = relay.options();
         let topic_id = TopicId::from_str(TOPIC)?;
-        let connection = Connection::create_with_opts(topic_id, RELAY_VEC, &SEED, options).await?;
+        let connection = Connection::create_with_opts(topic_id, &[], &SEED, options).await?;
         println!(
             "> user.online_peers(), {:#?}",
             connection.user.online_peers()
@@ -317,12 +814,215 @@ mod tests {
             connection.user_gossip_topic.is_joined()
         );
         println!("> finished [{:?}]", start.elapsed());
-        tokio::time::sleep(Duration::from_millis(250)).await;
-        println!("> press Ctrl+C to exit.");
-        tokio::signal::ctrl_c().await?;
+        assert!(connection.user_gossip_topic.is_joined());
         println!("> closing connection ...");
-        connection.user.close().await?;
-        connection.server_future.close().await?;
+        let endpoint = connection.user.endpoint().unwrap();
+        let report = connection.shutdown(Duration::from_secs(10)).await;
+        println!("> shutdown report, {report:#?}");
+        assert!(report.is_clean());
+        assert_eq!(report.closed_servers.len(), 1);
+        assert!(endpoint.is_closed());
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::released_slot_is_adopted -- --exact --nocapture'
+    async fn released_slot_is_adopted() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let mut staying_options = relay.options();
+        let mut staying_events = staying_options.subscribe_events();
+        let mut leaving_options = relay.options();
+        let mut leaving_events = leaving_options.subscribe_events();
+        let staying = Connection::create_with_opts(topic_id, &[], &seed, staying_options).await?;
+        let leaving = Connection::create_with_opts(topic_id, &[], &seed, leaving_options).await?;
+
+        let released_id = wait_for(&mut leaving_events, |event| match event {
+            ConnectionEvent::ServerConnected { server_id } => Some(server_id),
+            _ => None,
+        })
+        .await?;
+        leaving.server_future.close().await?;
+        let adopted_id = wait_for(&mut staying_events, |event| match event {
+            ConnectionEvent::SlotAdopted { server_id } => Some(server_id),
+            _ => None,
+        })
+        .await?;
+        assert_eq!(adopted_id, released_id);
+
+        leaving.user.close().await?;
+        staying.server_future.close().await?;
+        staying.user.close().await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::hosted_slots_stay_online -- --exact --nocapture'
+    async fn hosted_slots_stay_online() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let mut anchor_options = ConnectOptions { hosted_slots: vec![1, 2], ..relay.options() };
+        let mut anchor_events = anchor_options.subscribe_events();
+        let anchor = Connection::create_with_opts(topic_id, &[], &seed, anchor_options).await?;
+
+        let mut hosted = Vec::new();
+        while hosted.len() < 2 {
+            let server_id = wait_for(&mut anchor_events, |event| match event {
+                ConnectionEvent::ServerConnected { server_id } if server_id == 1 || server_id == 2 => Some(server_id),
+                _ => None,
+            })
+            .await?;
+            hosted.push(server_id);
+        }
+        hosted.sort_unstable();
+        assert_eq!(hosted, [1, 2]);
+        // A user searching only the hosted slots finds the swarm through them.
+        let options = ConnectOptions { starting_server_id: 1, n_server_to_search: 2, ..relay.options() };
+        let user = Connection::create_with_opts(topic_id, &[], &seed, options).await?;
+        assert!(user.user_gossip_topic.is_joined());
+
+        shutdown_all(vec![user, anchor]).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::simultaneous_binds_keep_one_holder -- --exact --nocapture'
+    async fn simultaneous_binds_keep_one_holder() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        // Alone on a single slot, neither hears the claim of the other.
+        let options = ConnectOptions { n_server_to_search: 1, ..relay.options() };
+        let (first, second) = tokio::join!(
+            Connection::create_with_opts(topic_id, &[], &seed, options.clone()),
+            Connection::create_with_opts(topic_id, &[], &seed, options),
+        );
+        let connections = vec![first?, second?];
+        let holders_of_slot_0 = || -> Result<usize> {
+            let mut holders = 0;
+            for connection in &connections {
+                let servers = lock_list(&connection.server_future.servers, "simultaneous_binds_keep_one_holder")?;
+                holders += servers.iter().filter(|server| server.id() == Some(0)).count();
+            }
+            Ok(holders)
+        };
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while holders_of_slot_0()? != 1 {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+        // The holder that is left keeps the slot.
+        tokio::time::sleep(HELD_INTERVAL * 2).await;
+        assert_eq!(holders_of_slot_0()?, 1);
+
+        shutdown_all(connections).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    async fn wait_for<T>(
+        events: &mut ConnectionEventReceiver,
+        filter: impl Fn(ConnectionEvent) -> Option<T>,
+    ) -> Result<T> {
+        let found = tokio::time::timeout(Duration::from_secs(20), async {
+            while let Some(event) = events.recv().await {
+                if let Some(found) = filter(event) {
+                    return Some(found);
+                }
+            }
+            None
+        });
+        found.await?.ok_or(anyhow::anyhow!("connection::tests::EventsClosed"))
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::bounded_slot_search -- --exact --nocapture'
+    async fn bounded_slot_search() -> Result<()> {
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let user = User::random_with_topic(topic_id, iroh::RelayMode::Disabled, DiscoveryConfig::default()).await?;
+        let (claims, _) = SlotClaims::listen(&user, &seed, ConnectOptions::default().rng(CLAIMS_RNG))?;
+        let mut args = ConnectionArgs {
+            topic_id,
+            relay_vec: Vec::new(),
+            my_addr: user.node_addr().await?.unwrap(),
+            seed,
+            server_addrs_map: HashMap::new(),
+            last_server_id: 10,
+            claims,
+            search_started: Instant::now(),
+            servers: ServerList::default(),
+            loops: LoopTasks::default(),
+            discovery: DiscoveryConfig::default(),
+        };
+        let options = ConnectOptions {
+            max_server_id: 9,
+            ..ConnectOptions::lan_only()
+        };
+        let error = get_offline_server_ids(&user, &mut args, &options).unwrap_err();
+        let Error::BootstrapExhausted(error) = error else {
+            panic!("expected a bounded slot search, got {error}");
+        };
+        assert_eq!(error.limit, SlotSearchLimit::MaxServerId(9));
+
+        let options = ConnectOptions {
+            slot_search_deadline: Duration::ZERO,
+            ..ConnectOptions::lan_only()
+        };
+        let error = get_offline_server_ids(&user, &mut args, &options).unwrap_err();
+        let Error::BootstrapExhausted(error) = error else {
+            panic!("expected a bounded slot search, got {error}");
+        };
+        assert_eq!(error.limit, SlotSearchLimit::Deadline(Duration::ZERO));
+        assert_eq!(error.searched_slots, 0);
+        user.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::lan_only -- --exact --nocapture'
+    async fn lan_only() -> Result<()> {
+        let options = ConnectOptions {
+            search_duration: Duration::from_secs(2),
+            n_server_to_search: 5,
+            ..ConnectOptions::lan_only()
+        };
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let first = Connection::create_with_opts(topic_id, RELAY_VEC, &seed, options.clone()).await?;
+        assert_eq!(first.user.relay_url(), None);
+        let second = Connection::create_with_opts(topic_id, RELAY_VEC, &seed, options).await?;
+        assert!(second.user_gossip_topic.is_joined());
+        second.user.close().await?;
+        second.server_future.close().await?;
+        first.user.close().await?;
+        first.server_future.close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::connection::tests::invalid_options -- --exact --nocapture'
+    async fn invalid_options() -> Result<()> {
+        let options = ConnectOptions {
+            n_server_to_search: 0,
+            ..ConnectOptions::lan_only()
+        };
+        let topic_id = TopicId::from_bytes(rand::random());
+        let user = User::random_with_topic(topic_id, iroh::RelayMode::Disabled, DiscoveryConfig::default()).await?;
+        let error = Connection::create_with_user(user.clone(), &[], &SEED, options).await.unwrap_err();
+        assert!(matches!(error, Error::Config { field: "slot_range", .. }), "{error}");
+        // The builder drops the repeated hosted slots, a struct literal is rejected.
+        assert_eq!(ConnectOptions::builder().hosted_slots([1, 1, 2]).build()?.hosted_slots, vec![1, 2]);
+        let twice = ConnectOptions { hosted_slots: vec![1, 1], ..ConnectOptions::lan_only() };
+        assert!(matches!(twice.validate(), Err(Error::Config { field: "hosted_slots", .. })));
+        user.close().await?;
         Ok(())
     }
 }
@@ -0,0 +1,34 @@
+use iroh::NodeId;
+use tokio::sync::mpsc;
+
+pub type ConnectionEventSender = mpsc::UnboundedSender<ConnectionEvent>;
+pub type ConnectionEventReceiver = mpsc::UnboundedReceiver<ConnectionEvent>;
+
+// What 'Connection' is doing while bootstrapping and afterwards.
+// Get them with 'ConnectOptions::subscribe_events'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    SearchingServers { server_ids: Vec<u64>, n_known_addresses: usize },
+    PeerFound,
+    NoPeerFound,
+    BecomingServer { server_id: u64 },
+    ServerSlotReplaced { old_id: u64, new_id: u64 },
+    // A neighbour closed the server of a slot, and this user took it over.
+    SlotReleased { server_id: u64 },
+    SlotAdopted { server_id: u64 },
+    // Another user held the same slot, this one closed its server, see 'Message::SlotHeld'.
+    SlotYielded { server_id: u64 },
+    ServerConnected { server_id: u64 },
+    UserConnected,
+    ServerNeighborUp { node_id: NodeId },
+    ServerNeighborDown { node_id: NodeId },
+    // Neighbours of the user, only reported by a 'SupervisedConnection'.
+    NeighborUp { node_id: NodeId },
+    NeighborDown { node_id: NodeId },
+    Reconnecting,
+    // The partition merger joins a peer that may belong to another swarm on the topic:
+    // the server of slot 'server_id' or the user holding it, None for a known address.
+    JoiningForeignPeer { server_id: Option<u64>, node_id: NodeId },
+    // A peer exchange was received, 'n_peers' of its peers passed the sanity checks.
+    PeersExchanged { from: NodeId, n_peers: usize },
+}
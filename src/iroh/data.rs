@@ -1,6 +1,8 @@
-use iroh::{Endpoint, RelayUrl, protocol::Router};
+use iroh::{Endpoint, RelayMode, RelayUrl, protocol::Router};
 use iroh_gossip::{net::Gossip, proto::TopicId};
 
+use super::DiscoveryConfig;
+
 #[derive(Debug, Clone)]
 pub struct IrohData {
     pub endpoint: Endpoint,
@@ -8,5 +10,8 @@ pub struct IrohData {
     pub router: Router,
 
     pub topic_id: TopicId,
-    pub relay_url: RelayUrl,
+    // None when running without relays (LAN-only mode).
+    pub relay_url: Option<RelayUrl>,
+    pub relay_mode: RelayMode,
+    pub discovery: DiscoveryConfig,
 }
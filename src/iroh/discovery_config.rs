@@ -0,0 +1,59 @@
+use iroh::{
+    discovery::pkarr::{PkarrPublisher, PkarrResolver, dht::DhtDiscovery},
+    endpoint::{Builder, TransportConfig, VarInt},
+};
+use url::Url;
+
+// How the user and server endpoints find nodes given by their id only. The local network
+// is always searched, the other backends are opt-in: they publish the node's relay and
+// direct addresses, signed by its key, to a pkarr relay or to the mainline DHT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DiscoveryConfig {
+    #[default]
+    LocalNetwork,
+    // Publishes to and resolves from a pkarr relay, like 'N0_DNS_PKARR_RELAY_PROD'.
+    PkarrRelay(Url),
+    // Publishes to and resolves from the mainline DHT, and the pkarr relay when given.
+    Dht(Option<Url>),
+}
+
+impl DiscoveryConfig {
+    pub(crate) fn apply(&self, builder: Builder) -> Builder {
+        let builder = builder.discovery_local_network().transport_config(transport_config());
+        match self.clone() {
+            DiscoveryConfig::LocalNetwork => builder,
+            DiscoveryConfig::PkarrRelay(pkarr_relay) => {
+                let resolver = PkarrResolver::new(pkarr_relay.clone());
+                builder
+                    .add_discovery(move |secret_key| Some(PkarrPublisher::new(secret_key.clone(), pkarr_relay)))
+                    .add_discovery(move |_| Some(resolver))
+            }
+            DiscoveryConfig::Dht(pkarr_relay) => builder.add_discovery(move |secret_key| {
+                let mut dht = DhtDiscovery::builder().secret_key(secret_key.clone()).dht(true);
+                if let Some(pkarr_relay) = pkarr_relay {
+                    dht = dht.pkarr_relay(pkarr_relay);
+                }
+                match dht.build() {
+                    Ok(dht) => Some(dht),
+                    Err(e) => {
+                        // The endpoint still works, without the DHT.
+                        tracing::warn!("could not start the dht discovery '{e}'");
+                        None
+                    }
+                }
+            }),
+        }
+    }
+}
+
+// A connection whose handshake is closed by the peer can stay around until it idles out,
+// and 'Endpoint::close' waits for it, so the 30s default would stall every shutdown.
+// The 1s keep alive of iroh keeps the live connections well under 'MAX_IDLE_MS'.
+const MAX_IDLE_MS: u32 = 5_000;
+
+pub(crate) fn transport_config() -> TransportConfig {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(std::time::Duration::from_secs(1)));
+    config.max_idle_timeout(Some(VarInt::from_u32(MAX_IDLE_MS).into()));
+    config
+}
@@ -1,9 +1,25 @@
 use iroh::{NodeAddr, RelayUrl};
+use iroh_gossip::proto::TopicId;
 
-use super::generate_server_secret_key;
+use super::{ServerKeys, generate_server_secret_key};
 
-pub fn get_server_addr(id: u64, relay_url: RelayUrl, seed: &[u8; 32]) -> NodeAddr {
-    let secret_key = generate_server_secret_key(id, seed);
+// 'seed' is the seed of the swarm, the slot seed of 'topic_id' is derived from it.
+pub fn get_server_addr(
+    id: u64,
+    relay_url: Option<RelayUrl>,
+    seed: &[u8; 32],
+    topic_id: &TopicId,
+    server_keys: ServerKeys,
+) -> NodeAddr {
+    slot_server_addr(id, relay_url, &server_keys.slot_seed(seed, topic_id))
+}
+
+// 'slot_seed' is derived already, see 'ServerKeys::slot_seed'.
+pub(crate) fn slot_server_addr(id: u64, relay_url: Option<RelayUrl>, slot_seed: &[u8; 32]) -> NodeAddr {
+    let secret_key = generate_server_secret_key(id, slot_seed);
     let node_id = secret_key.public();
-    NodeAddr::new(node_id).with_relay_url(relay_url)
+    match relay_url {
+        None => NodeAddr::new(node_id),
+        Some(relay_url) => NodeAddr::new(node_id).with_relay_url(relay_url),
+    }
 }
@@ -1,22 +1,31 @@
-use std::str::FromStr;
+use crate::Result;
+use iroh::NodeAddr;
+use iroh_gossip::proto::TopicId;
 
-use anyhow::Result;
-use iroh::{NodeAddr, RelayUrl};
-
-use super::get_server_addr;
+use super::{ServerKeys, get_server_addr::slot_server_addr, get_server_relay};
 
+// 'seed' is the seed of the swarm, the slot seed of 'topic_id' is derived from it.
 pub fn get_server_addresses(
     id_vec: &[u64],
     relay_vec: &[String],
     seed: &[u8; 32],
+    topic_id: &TopicId,
+    server_keys: ServerKeys,
+) -> Result<Vec<NodeAddr>> {
+    slot_server_addresses(id_vec, relay_vec, &server_keys.slot_seed(seed, topic_id))
+}
+
+// 'slot_seed' is derived already, see 'ServerKeys::slot_seed'.
+pub(crate) fn slot_server_addresses(
+    id_vec: &[u64],
+    relay_vec: &[String],
+    slot_seed: &[u8; 32],
 ) -> Result<Vec<NodeAddr>> {
     let mut addresses: Vec<NodeAddr> = Vec::new();
     for &id in id_vec {
-        for relay_str in relay_vec {
-            let relay_url = RelayUrl::from_str(relay_str)?;
-            let server_addr = get_server_addr(id, relay_url, seed);
-            addresses.push(server_addr);
-        }
+        let relay_url = get_server_relay(id, relay_vec)?;
+        let server_addr = slot_server_addr(id, relay_url, slot_seed);
+        addresses.push(server_addr);
     }
     Ok(addresses)
 }
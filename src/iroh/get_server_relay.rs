@@ -0,0 +1,34 @@
+use std::str::FromStr;
+
+use crate::Result;
+use iroh::RelayUrl;
+
+// Every server slot lives on a fixed relay of the list, so users homed on any relay
+// know where to dial it, and the slots are spread across all the relays.
+// Without relays (LAN-only mode) the servers are found through local network discovery.
+pub fn get_server_relay(id: u64, relay_vec: &[String]) -> Result<Option<RelayUrl>> {
+    if relay_vec.is_empty() {
+        return Ok(None);
+    }
+    let index = (id % relay_vec.len() as u64) as usize;
+    Ok(Some(RelayUrl::from_str(&relay_vec[index])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // run test by using: 'cargo test iroh::get_server_relay::tests::spread_across_relays -- --exact --nocapture'
+    fn spread_across_relays() -> Result<()> {
+        let relay_vec: Vec<String> = vec![
+            "https://euw1-1.relay.iroh.network./".to_string(),
+            "https://use1-1.relay.iroh.network./".to_string(),
+        ];
+        assert_eq!(get_server_relay(0, &relay_vec)?, Some(RelayUrl::from_str(&relay_vec[0])?));
+        assert_eq!(get_server_relay(1, &relay_vec)?, Some(RelayUrl::from_str(&relay_vec[1])?));
+        assert_eq!(get_server_relay(4, &relay_vec)?, Some(RelayUrl::from_str(&relay_vec[0])?));
+        assert_eq!(get_server_relay(0, &[])?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{Error, Result};
+use ed25519_dalek::Signature;
+use iroh::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+const DOMAIN: &[u8] = b"lele::device_certificate";
+
+// Binds a device key (the one used by the endpoint) to a root identity key.
+// The root key can stay offline, only the certificate travels with the messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCertificate {
+    root: PublicKey,
+    device: PublicKey,
+    signature: Signature,
+}
+
+impl DeviceCertificate {
+    pub fn create(root_secret_key: &SecretKey, device: PublicKey) -> Self {
+        let root = root_secret_key.public();
+        let signature = root_secret_key.sign(&certificate_bytes(&root, &device));
+        DeviceCertificate {
+            root,
+            device,
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        self.root
+            .verify(&certificate_bytes(&self.root, &self.device), &self.signature)
+            .map_err(|_| Error::Signature { at: "device_certificate::verify", signed: "certificate" })
+    }
+
+    pub fn root(&self) -> PublicKey {
+        self.root
+    }
+
+    pub fn device(&self) -> PublicKey {
+        self.device
+    }
+}
+
+fn certificate_bytes(root: &PublicKey, device: &PublicKey) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(DOMAIN.len() + 64);
+    bytes.extend_from_slice(DOMAIN);
+    bytes.extend_from_slice(root.as_bytes());
+    bytes.extend_from_slice(device.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::device_certificate::tests::verify_certificate -- --exact --nocapture'
+    fn verify_certificate() -> Result<()> {
+        let root = SecretKey::generate(rand::rngs::OsRng);
+        let device = SecretKey::generate(rand::rngs::OsRng);
+        let other = SecretKey::generate(rand::rngs::OsRng);
+        let certificate = DeviceCertificate::create(&root, device.public());
+        certificate.verify()?;
+        assert_eq!(certificate.root(), root.public());
+
+        let mut forged = certificate.clone();
+        forged.device = other.public();
+        assert!(forged.verify().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use iroh::PublicKey;
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+// Ephemeral messages (typing indicators, cursor positions, ...) are only useful live,
+// storage and history features never keep them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Durability {
+    #[default]
+    Durable,
+    Ephemeral,
+}
+
+// A verified message, together with the metadata signed by its sender.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub author: PublicKey,
+    pub device: PublicKey,
+    pub message: Message,
+    pub sent_at: SystemTime,
+    pub ttl: Option<Duration>,
+    pub durability: Durability,
+}
+
+impl Envelope {
+    // A ttl too large for the platform clock never expires.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.ttl.and_then(|ttl| self.sent_at.checked_add(ttl))
+    }
+
+    // Uses the local clock, so it is only as precise as the clocks of the two peers.
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at() {
+            None => false,
+            Some(expires_at) => SystemTime::now() > expires_at,
+        }
+    }
+
+    pub fn is_ephemeral(&self) -> bool {
+        self.durability == Durability::Ephemeral
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// None when the time does not fit in the platform clock.
+pub(crate) fn from_millis(millis: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+}
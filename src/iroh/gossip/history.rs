@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+
+use super::Envelope;
+
+// In-memory log of the last received messages, ephemeral and expired ones are never kept.
+#[derive(Debug, Clone)]
+pub struct History {
+    capacity: usize,
+    envelopes: VecDeque<Envelope>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            envelopes: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // Returns true if the envelope was stored.
+    pub fn record(&mut self, envelope: &Envelope) -> bool {
+        if self.capacity == 0 || envelope.is_ephemeral() || envelope.is_expired() {
+            return false;
+        }
+        if self.envelopes.len() == self.capacity {
+            self.envelopes.pop_front();
+        }
+        self.envelopes.push_back(envelope.clone());
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Envelope> {
+        self.envelopes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.envelopes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envelopes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iroh::gossip::{Durability, Message};
+    use iroh::SecretKey;
+    use std::time::{Duration, SystemTime};
+
+    fn envelope(durability: Durability, ttl: Option<Duration>) -> Envelope {
+        let key = SecretKey::generate(rand::rngs::OsRng).public();
+        Envelope {
+            author: key,
+            device: key,
+            message: Message::text("hello"),
+            sent_at: SystemTime::now() - Duration::from_secs(1),
+            ttl,
+            durability,
+        }
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::history::tests::skip_ephemeral -- --exact --nocapture'
+    fn skip_ephemeral() {
+        let mut history = History::new(2);
+        assert!(!history.record(&envelope(Durability::Ephemeral, None)));
+        assert!(!history.record(&envelope(Durability::Durable, Some(Duration::ZERO))));
+        assert!(history.record(&envelope(Durability::Durable, None)));
+        assert!(history.record(&envelope(Durability::Durable, Some(Duration::from_secs(60)))));
+        assert!(history.record(&envelope(Durability::Durable, None)));
+        assert_eq!(history.len(), 2);
+    }
+}
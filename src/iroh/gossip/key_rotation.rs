@@ -0,0 +1,92 @@
+use crate::{Error, Result};
+use ed25519_dalek::Signature;
+use iroh::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+const DOMAIN: &[u8] = b"lele::key_rotation";
+
+// A succession statement: 'old' hands its identity over to 'new'.
+// It is signed by both keys, so it can only be made by someone holding both.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    old: PublicKey,
+    new: PublicKey,
+    old_signature: Signature,
+    new_signature: Signature,
+}
+
+impl KeyRotation {
+    pub fn create(old_secret_key: &SecretKey, new_secret_key: &SecretKey) -> Result<Self> {
+        let old = old_secret_key.public();
+        let new = new_secret_key.public();
+        if old == new {
+            return Err(Error::Rejected { at: "key_rotation::create", reason: "same old and new key" });
+        }
+        let statement = statement_bytes(&old, &new);
+        Ok(KeyRotation {
+            old,
+            new,
+            old_signature: old_secret_key.sign(&statement),
+            new_signature: new_secret_key.sign(&statement),
+        })
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        if self.old == self.new {
+            return Err(Error::Rejected { at: "key_rotation::verify", reason: "same old and new key" });
+        }
+        let statement = statement_bytes(&self.old, &self.new);
+        self.old
+            .verify(&statement, &self.old_signature)
+            .map_err(|_| Error::Signature { at: "key_rotation::verify", signed: "old key" })?;
+        self.new
+            .verify(&statement, &self.new_signature)
+            .map_err(|_| Error::Signature { at: "key_rotation::verify", signed: "new key" })?;
+        Ok(())
+    }
+
+    pub fn old_key(&self) -> PublicKey {
+        self.old
+    }
+
+    pub fn new_key(&self) -> PublicKey {
+        self.new
+    }
+}
+
+fn statement_bytes(old: &PublicKey, new: &PublicKey) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(DOMAIN.len() + 64);
+    bytes.extend_from_slice(DOMAIN);
+    bytes.extend_from_slice(old.as_bytes());
+    bytes.extend_from_slice(new.as_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::key_rotation::tests::valid_rotation -- --exact --nocapture'
+    fn valid_rotation() -> Result<()> {
+        let old = SecretKey::generate(rand::rngs::OsRng);
+        let new = SecretKey::generate(rand::rngs::OsRng);
+        let rotation = KeyRotation::create(&old, &new)?;
+        rotation.verify()?;
+        assert_eq!(rotation.old_key(), old.public());
+        assert_eq!(rotation.new_key(), new.public());
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::key_rotation::tests::tampered_rotation -- --exact --nocapture'
+    fn tampered_rotation() -> Result<()> {
+        let old = SecretKey::generate(rand::rngs::OsRng);
+        let new = SecretKey::generate(rand::rngs::OsRng);
+        let attacker = SecretKey::generate(rand::rngs::OsRng);
+        let mut rotation = KeyRotation::create(&old, &new)?;
+        rotation.new = attacker.public();
+        assert!(rotation.verify().is_err());
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
-use anyhow::{Result, anyhow};
+use crate::{Error, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::iroh::User;
+use iroh::{NodeAddr, NodeId, PublicKey, SecretKey};
+
+use super::KeyRotation;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 
@@ -9,6 +12,19 @@ pub enum Message {
     AboutMe { username: String },
     SimpleText { text: String },
     RequestImg { image_name: String },
+    KeyRotation { rotation: KeyRotation },
+    // Moderation messages, only the ones sent by an admin of the topic are enforced.
+    Kick { target: PublicKey },
+    Ban { target: PublicKey },
+    Mute { target: PublicKey },
+    // Control messages of the bootstrap, 'Receiver' never hands them to the app.
+    SlotClaim { slot: u64 },
+    SlotRelease { slot: u64 },
+    // Sent now and then by the server of a slot for its user 'holder', so two holders of
+    // the same slot find each other.
+    SlotHeld { slot: u64, holder: NodeId },
+    // A sample of the peers known by the sender, see 'PeerExchange'.
+    PeerExchange { peers: Vec<NodeAddr> },
 }
 
 #[rustfmt::skip] // Not the best, but it works
@@ -16,7 +32,7 @@ pub enum Message {
 impl Message {
     pub fn about_me(user: &User) -> Result<Message> {
         match user {
-            User::Empty => return Err(anyhow!("message::about_me::UserIsEmpty")),
+            User::Empty => return Err(Error::EmptyInstance("message::about_me")),
             User::Data {..} => {},
         }
         Ok(Message::AboutMe { username: user.name().unwrap() })
@@ -29,5 +45,41 @@ impl Message {
     pub fn req_img(image_name: &str) -> Message {
         Message::RequestImg{ image_name: image_name.to_string() }
     }
+
+    pub fn key_rotation(old_secret_key: &SecretKey, new_secret_key: &SecretKey) -> Result<Message> {
+        Ok(Message::KeyRotation{ rotation: KeyRotation::create(old_secret_key, new_secret_key)? })
+    }
+
+    pub fn kick(target: PublicKey) -> Message {
+        Message::Kick{ target }
+    }
+
+    pub fn ban(target: PublicKey) -> Message {
+        Message::Ban{ target }
+    }
+
+    pub fn mute(target: PublicKey) -> Message {
+        Message::Mute{ target }
+    }
+
+    pub fn slot_claim(slot: u64) -> Message {
+        Message::SlotClaim{ slot }
+    }
+
+    pub fn slot_release(slot: u64) -> Message {
+        Message::SlotRelease{ slot }
+    }
+
+    pub fn slot_held(slot: u64, holder: NodeId) -> Message {
+        Message::SlotHeld{ slot, holder }
+    }
+
+    pub fn peer_exchange(peers: Vec<NodeAddr>) -> Message {
+        Message::PeerExchange{ peers }
+    }
+
+    pub fn is_control(&self) -> bool {
+        matches!(self, Message::SlotClaim{ .. } | Message::SlotRelease{ .. } | Message::SlotHeld{ .. } | Message::PeerExchange{ .. })
+    }
     
 }
@@ -1,7 +1,23 @@
+mod device_certificate;
+mod envelope;
+mod history;
+mod key_rotation;
 mod message;
+mod moderation;
+mod receiver;
 mod signed_message;
 mod sender;
+mod trust_store;
 
+pub use device_certificate::DeviceCertificate;
+pub use envelope::Durability;
+pub use envelope::Envelope;
+pub use history::History;
+pub use key_rotation::KeyRotation;
 pub use message::Message;
+pub use moderation::Moderation;
+pub use moderation::KICK_DURATION;
+pub use receiver::Receiver;
 pub use signed_message::SignedMessage;
 pub use sender::Sender;
+pub use trust_store::TrustStore;
@@ -0,0 +1,182 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Result};
+use iroh::PublicKey;
+use iroh_gossip::proto::TopicId;
+use serde::{Deserialize, Serialize};
+
+use super::Message;
+
+pub const KICK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// Moderation decisions taken by the admins of a topic.
+// 'kicked' stores the unix time (in seconds) of the kick.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ModerationState {
+    topic_id: TopicId,
+    banned: HashSet<PublicKey>,
+    muted: HashSet<PublicKey>,
+    kicked: HashMap<PublicKey, u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Moderation {
+    admins: HashSet<PublicKey>,
+    state: ModerationState,
+    path: Option<PathBuf>,
+}
+
+impl Moderation {
+    pub fn new(topic_id: TopicId, admins: &[PublicKey]) -> Self {
+        Moderation {
+            admins: admins.iter().cloned().collect(),
+            state: ModerationState {
+                topic_id,
+                banned: HashSet::new(),
+                muted: HashSet::new(),
+                kicked: HashMap::new(),
+            },
+            path: None,
+        }
+    }
+
+    // Loads previous decisions from 'path' (if it exists) and saves every new one there.
+    pub fn with_storage(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            let bytes = std::fs::read(&path)?;
+            let state: ModerationState = postcard::from_bytes(&bytes)?;
+            if state.topic_id != self.state.topic_id {
+                return Err(Error::Rejected { at: "moderation::with_storage", reason: "storage of another topic" });
+            }
+            self.state = state;
+        }
+        self.path = Some(path);
+        Ok(self)
+    }
+
+    pub fn is_admin(&self, key: &PublicKey) -> bool {
+        self.admins.contains(key)
+    }
+
+    pub fn is_banned(&self, key: &PublicKey) -> bool {
+        self.state.banned.contains(key)
+    }
+
+    pub fn is_muted(&self, key: &PublicKey) -> bool {
+        self.state.muted.contains(key)
+    }
+
+    pub fn is_kicked(&self, key: &PublicKey) -> bool {
+        match self.state.kicked.get(key) {
+            None => false,
+            Some(kicked_at) => now_secs() < kicked_at + KICK_DURATION.as_secs(),
+        }
+    }
+
+    // Returns true if 'message' from 'author' should reach the app.
+    // Moderation messages from admins are applied (and saved) before being delivered,
+    // the ones from everybody else are dropped.
+    pub fn check(&mut self, author: &PublicKey, message: &Message) -> Result<bool> {
+        if self.is_banned(author) || self.is_kicked(author) {
+            return Ok(false);
+        }
+        match message {
+            Message::Kick { target } | Message::Ban { target } | Message::Mute { target } => {
+                if !self.is_admin(author) || self.is_admin(target) {
+                    return Ok(false);
+                }
+                self.apply(message)?;
+                Ok(true)
+            }
+            Message::KeyRotation { .. } => Ok(true),
+            _ => Ok(!self.is_muted(author)),
+        }
+    }
+
+    fn apply(&mut self, message: &Message) -> Result<()> {
+        match message {
+            Message::Kick { target } => {
+                self.state.kicked.insert(*target, now_secs());
+            }
+            Message::Ban { target } => {
+                self.state.banned.insert(*target);
+            }
+            Message::Mute { target } => {
+                self.state.muted.insert(*target);
+            }
+            _ => return Ok(()),
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            let bytes = postcard::to_stdvec(&self.state)?;
+            std::fs::write(path, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    fn random_key() -> PublicKey {
+        SecretKey::generate(rand::rngs::OsRng).public()
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::moderation::tests::only_admins_moderate -- --exact --nocapture'
+    fn only_admins_moderate() -> Result<()> {
+        let topic_id = TopicId::from_bytes(rand::random());
+        let (admin, user, troll) = (random_key(), random_key(), random_key());
+        let mut moderation = Moderation::new(topic_id, &[admin]);
+
+        assert!(!moderation.check(&troll, &Message::Ban { target: user })?);
+        assert!(moderation.check(&user, &Message::text("hi"))?);
+        assert!(!moderation.check(&troll, &Message::Ban { target: admin })?);
+
+        assert!(moderation.check(&admin, &Message::Mute { target: troll })?);
+        assert!(!moderation.check(&troll, &Message::text("spam"))?);
+        assert!(moderation.check(&admin, &Message::Kick { target: troll })?);
+        assert!(moderation.is_kicked(&troll));
+        assert!(moderation.check(&admin, &Message::Ban { target: troll })?);
+        assert!(!moderation.check(&troll, &Message::Kick { target: user })?);
+        assert!(moderation.check(&user, &Message::text("bye troll"))?);
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::moderation::tests::decisions_survive_restart -- --exact --nocapture'
+    fn decisions_survive_restart() -> Result<()> {
+        let topic_id = TopicId::from_bytes(rand::random());
+        let (admin, troll) = (random_key(), random_key());
+        let path = std::env::temp_dir().join(format!("lele_moderation_{}", &topic_id.to_string()[..10]));
+        let _ = std::fs::remove_file(&path);
+
+        let mut moderation = Moderation::new(topic_id, &[admin]).with_storage(&path)?;
+        moderation.check(&admin, &Message::Ban { target: troll })?;
+        drop(moderation);
+
+        let moderation = Moderation::new(topic_id, &[admin]).with_storage(&path)?;
+        assert!(moderation.is_banned(&troll));
+        let other_topic = TopicId::from_bytes(rand::random());
+        assert!(Moderation::new(other_topic, &[admin]).with_storage(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::Result;
+use iroh::PublicKey;
+use iroh_gossip::net::{Event, GossipEvent, GossipReceiver};
+use n0_future::TryStreamExt;
+use tokio::sync::mpsc;
+
+use crate::iroh::gossip::{Envelope, History, Message, Moderation, SignedMessage, TrustStore};
+
+// The receiving side of 'Sender'.
+// It verifies every message, drops the expired and the control ones, follows key
+// rotations and enforces moderation, so the app only sees messages it should act upon.
+#[derive(Debug)]
+pub struct Receiver {
+    events: EventSource,
+    trust_store: TrustStore,
+    moderation: Option<Moderation>,
+    history: Option<History>,
+}
+
+impl Receiver {
+    pub fn create(gossip_receiver: GossipReceiver) -> Self {
+        Receiver::from_source(EventSource::Gossip(gossip_receiver))
+    }
+
+    // Used when the events are forwarded by a supervisor that may rejoin the topic.
+    pub(crate) fn from_channel(channel: mpsc::Receiver<Event>) -> Self {
+        Receiver::from_source(EventSource::Channel(channel))
+    }
+
+    fn from_source(events: EventSource) -> Self {
+        Receiver {
+            events,
+            trust_store: TrustStore::new(),
+            moderation: None,
+            history: None,
+        }
+    }
+
+    pub fn with_trust_store(mut self, trust_store: TrustStore) -> Self {
+        self.trust_store = trust_store;
+        self
+    }
+
+    pub fn with_moderation(mut self, moderation: Moderation) -> Self {
+        self.moderation = Some(moderation);
+        self
+    }
+
+    // Every accepted durable message is also recorded here.
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    // Returns the next accepted message and its author, 'None' when the topic is closed.
+    pub async fn next(&mut self) -> Result<Option<(PublicKey, Message)>> {
+        let envelope = self.next_envelope().await?;
+        Ok(envelope.map(|envelope| (envelope.author, envelope.message)))
+    }
+
+    // Messages that cannot be verified, are expired or are rejected by moderation are skipped.
+    pub async fn next_envelope(&mut self) -> Result<Option<Envelope>> {
+        while let Some(event) = self.events.next().await? {
+            let Event::Gossip(GossipEvent::Received(msg)) = event else {
+                continue;
+            };
+            let mut envelope = match SignedMessage::verify_and_decode_envelope(&msg.content) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    tracing::debug!(delivered_from = %msg.delivered_from, "dropped invalid message: {e}");
+                    continue;
+                }
+            };
+            if envelope.message.is_control() {
+                continue;
+            }
+            if envelope.is_expired() {
+                tracing::trace!(device = %envelope.device, "dropped expired message");
+                continue;
+            }
+            if let Message::KeyRotation { rotation } = &envelope.message
+                && let Err(e) = self.trust_store.record(rotation)
+            {
+                tracing::debug!(device = %envelope.device, "dropped key rotation: {e}");
+                continue;
+            }
+            envelope.author = self.trust_store.resolve(&envelope.author);
+            envelope.message = self.resolve_target(envelope.message);
+            if let Some(moderation) = &mut self.moderation
+                && !moderation.check(&envelope.author, &envelope.message)?
+            {
+                tracing::debug!(author = %envelope.author, "dropped message by moderation");
+                continue;
+            }
+            if let Some(history) = &mut self.history {
+                history.record(&envelope);
+            }
+            return Ok(Some(envelope));
+        }
+        Ok(None)
+    }
+
+    pub fn trust_store(&self) -> &TrustStore {
+        &self.trust_store
+    }
+
+    pub fn moderation(&self) -> Option<&Moderation> {
+        self.moderation.as_ref()
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    // Moderation decisions are taken against the first key of a person,
+    // so rotating keys does not lift a ban.
+    fn resolve_target(&self, message: Message) -> Message {
+        match message {
+            Message::Kick { target } => Message::Kick { target: self.trust_store.resolve(&target) },
+            Message::Ban { target } => Message::Ban { target: self.trust_store.resolve(&target) },
+            Message::Mute { target } => Message::Mute { target: self.trust_store.resolve(&target) },
+            message => message,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum EventSource {
+    Gossip(GossipReceiver),
+    Channel(mpsc::Receiver<Event>),
+}
+
+impl EventSource {
+    async fn next(&mut self) -> Result<Option<Event>> {
+        match self {
+            EventSource::Gossip(gossip_receiver) => Ok(gossip_receiver.try_next().await?),
+            EventSource::Channel(channel) => Ok(channel.recv().await),
+        }
+    }
+}
@@ -1,35 +1,190 @@
-use anyhow::{anyhow, Result};
-use iroh::SecretKey;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{Error, Result};
+use iroh::{PublicKey, SecretKey};
 use iroh_gossip::net::GossipSender;
 
-use crate::iroh::{gossip::{Message, SignedMessage}, User};
+use crate::iroh::{gossip::{DeviceCertificate, Durability, Message, SignedMessage}, User};
 
 #[derive(Debug, Clone)]
 pub struct Sender {
     // user: &'a User,
+    // Shared between clones, so a key rotation reaches every one of them.
+    signing: Arc<RwLock<Signing>>,
+    // Shared between clones, so a reconnection swaps it for every one of them.
+    gossip_sender: Arc<RwLock<GossipSender>>,
+}
+
+#[derive(Debug, Clone)]
+struct Signing {
     secret_key: SecretKey,
-    gossip_sender: GossipSender,
+    certificate: Option<DeviceCertificate>,
 }
 
 impl Sender {
     pub fn create(user: &User, gossip_sender: GossipSender) -> Result<Self> {
         match user {
-            User::Empty => return Err(anyhow!("todo::create::UserIsEmpty")),
+            User::Empty => return Err(Error::EmptyInstance("sender::create")),
             User::Data { .. } => {}
         }
         let secret_key = match user.secret_key()? {
-            None => return Err(anyhow!("todo::broadcast::SecretKeyNotFound")),
+            None => return Err(Error::EmptyInstance("sender::create")),
             Some(secret_key) => secret_key,
         };
         Ok(Sender {
-            secret_key,
-            gossip_sender,
+            signing: Arc::new(RwLock::new(Signing { secret_key, certificate: None })),
+            gossip_sender: Arc::new(RwLock::new(gossip_sender)),
         })
     }
 
     pub async fn broadcast(&self, message: &Message) -> Result<()> {
-        let encoded_message = SignedMessage::sign_and_encode(&self.secret_key, message)?;
-        self.gossip_sender.broadcast(encoded_message).await?;
+        self.broadcast_with(message, Durability::Durable, None).await
+    }
+
+    // For messages that are worthless after a while, like typing indicators.
+    pub async fn broadcast_ephemeral(&self, message: &Message, ttl: Duration) -> Result<()> {
+        self.broadcast_with(message, Durability::Ephemeral, Some(ttl)).await
+    }
+
+    #[tracing::instrument(name = "broadcast", skip_all, fields(from = %self.public_key().fmt_short(), ?durability))]
+    pub async fn broadcast_with(
+        &self,
+        message: &Message,
+        durability: Durability,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let signing = self.signing()?;
+        let encoded_message = SignedMessage::sign_and_encode_with_opts(
+            &signing.secret_key,
+            signing.certificate.as_ref(),
+            message,
+            durability,
+            ttl,
+        )?;
+        let gossip_sender = self.gossip_sender()?;
+        tracing::trace!(n_bytes = encoded_message.len(), "broadcasting message");
+        gossip_sender.broadcast(encoded_message).await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Announces the new key (signed by both keys) and signs every following message with it,
+    // from this sender and its clones. A device certificate is bound to the old key, so it is dropped.
+    pub async fn rotate_key(&self, new_secret_key: SecretKey) -> Result<()> {
+        let old_secret_key = self.signing()?.secret_key;
+        let message = Message::key_rotation(&old_secret_key, &new_secret_key)?;
+        self.broadcast(&message).await?;
+        match self.signing.write() {
+            Err(_) => Err(Error::LockPoisoned("sender::rotate_key")),
+            Ok(mut signing) => {
+                *signing = Signing { secret_key: new_secret_key, certificate: None };
+                Ok(())
+            }
+        }
+    }
+
+    // Attaches a certificate to every following message, so receivers see them as sent by its root.
+    pub fn set_certificate(&mut self, certificate: DeviceCertificate) -> Result<&mut Self> {
+        let mut signing = match self.signing.write() {
+            Err(_) => return Err(Error::LockPoisoned("sender::set_certificate")),
+            Ok(signing) => signing,
+        };
+        if certificate.device() != signing.secret_key.public() {
+            return Err(Error::Rejected { at: "sender::set_certificate", reason: "certificate of another device" });
+        }
+        certificate.verify()?;
+        signing.certificate = Some(certificate);
+        drop(signing);
+        Ok(self)
+    }
+
+    // Read from a poisoned lock too: the key is only ever replaced as a whole.
+    pub fn public_key(&self) -> PublicKey {
+        match self.signing.read() {
+            Err(poisoned) => poisoned.into_inner().secret_key.public(),
+            Ok(signing) => signing.secret_key.public(),
+        }
+    }
+
+    fn signing(&self) -> Result<Signing> {
+        match self.signing.read() {
+            Err(_) => Err(Error::LockPoisoned("sender::signing")),
+            Ok(signing) => Ok(signing.clone()),
+        }
+    }
+
+    pub fn gossip_sender(&self) -> Result<GossipSender> {
+        match self.gossip_sender.read() {
+            Err(_) => Err(Error::LockPoisoned("sender::gossip_sender")),
+            Ok(gossip_sender) => Ok(gossip_sender.clone()),
+        }
+    }
+
+    pub(crate) fn replace_gossip_sender(&self, gossip_sender: GossipSender) -> Result<()> {
+        match self.gossip_sender.write() {
+            Err(_) => Err(Error::LockPoisoned("sender::replace_gossip_sender")),
+            Ok(mut current) => {
+                *current = gossip_sender;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        iroh::gossip::Receiver,
+        tests::local_relay::{LocalRelay, close_users},
+    };
+    use anyhow::Result;
+    use iroh_gossip::proto::TopicId;
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::gossip::sender::tests::rotation_reaches_clones -- --exact --nocapture'
+    async fn rotation_reaches_clones() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let mut users = Vec::new();
+        let mut senders = Vec::new();
+        let mut receivers = Vec::new();
+        for connection in relay.spawn_users(2, topic_id, &seed).await? {
+            let (user, server_future, user_gossip_topic) = connection.into_parts();
+            let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+            senders.push(Sender::create(&user, gossip_sender)?);
+            receivers.push(Receiver::create(gossip_receiver));
+            users.push((user, server_future));
+        }
+        let clone = senders[0].clone();
+        let old_public_key = senders[0].public_key();
+        let new_secret_key = SecretKey::generate(rand::rngs::OsRng);
+        senders[0].rotate_key(new_secret_key.clone()).await?;
+        assert_eq!(clone.public_key(), new_secret_key.public());
+
+        let message = Message::text("sent by the clone");
+        clone.broadcast(&message).await?;
+        let envelope = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match receivers[1].next_envelope().await? {
+                    Some(envelope) if envelope.message == message => return Ok(Some(envelope)),
+                    Some(_) => continue,
+                    None => return Ok::<_, Error>(None),
+                }
+            }
+        })
+        .await??
+        .expect("the receiver ended");
+        // Signed with the new key, and still the same person for the receiver.
+        assert_eq!(envelope.device, new_secret_key.public());
+        assert_eq!(envelope.author, old_public_key);
+
+        drop((senders, receivers, clone));
+        close_users(users).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+}
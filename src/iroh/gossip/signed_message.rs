@@ -1,37 +1,216 @@
-use anyhow::Result;
+use std::time::Duration;
+
+use crate::{Error, Result};
 use bytes::Bytes;
 use ed25519_dalek::Signature;
 use iroh::{PublicKey, SecretKey};
 use serde::{Deserialize, Serialize};
 
-use super::Message;
+use super::{
+    DeviceCertificate, Durability, Envelope, Message,
+    envelope::{from_millis, now_millis},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignedMessage {
     from: PublicKey,
     data: Bytes,
+    sent_at: u64,
+    ttl: Option<u64>,
+    durability: Durability,
     signature: Signature,
+    certificate: Option<DeviceCertificate>,
 }
 
 impl SignedMessage {
+    // Returns the author of the message: the root identity when the message
+    // carries a valid device certificate, the signing key otherwise.
     pub fn verify_and_decode(bytes: &[u8]) -> Result<(PublicKey, Message)> {
+        let envelope = Self::verify_and_decode_envelope(bytes)?;
+        Ok((envelope.author, envelope.message))
+    }
+
+    pub fn verify_and_decode_with_device(bytes: &[u8]) -> Result<(PublicKey, PublicKey, Message)> {
+        let envelope = Self::verify_and_decode_envelope(bytes)?;
+        Ok((envelope.author, envelope.device, envelope.message))
+    }
+
+    pub fn verify_and_decode_envelope(bytes: &[u8]) -> Result<Envelope> {
         let signed_message: Self = postcard::from_bytes(bytes)?;
         let key: PublicKey = signed_message.from;
-        key.verify(&signed_message.data, &signed_message.signature)?;
+        let signed_bytes = signed_bytes(
+            &signed_message.data,
+            signed_message.sent_at,
+            signed_message.ttl,
+            signed_message.durability,
+        )?;
+        key.verify(&signed_bytes, &signed_message.signature)
+            .map_err(|_| Error::Signature { at: "signed_message::verify_and_decode", signed: "message" })?;
         let message: Message = postcard::from_bytes(&signed_message.data)?;
-        Ok((signed_message.from, message))
+        let author = match &signed_message.certificate {
+            None => key,
+            Some(certificate) => {
+                if certificate.device() != key {
+                    return Err(Error::Rejected {
+                        at: "signed_message::verify_and_decode",
+                        reason: "certificate of another device",
+                    });
+                }
+                certificate.verify()?;
+                certificate.root()
+            }
+        };
+        let sent_at = from_millis(signed_message.sent_at).ok_or(Error::Rejected {
+            at: "signed_message::verify_and_decode",
+            reason: "send time out of the clock range",
+        })?;
+        Ok(Envelope {
+            author,
+            device: key,
+            message,
+            sent_at,
+            ttl: signed_message.ttl.map(Duration::from_millis),
+            durability: signed_message.durability,
+        })
     }
 
     pub fn sign_and_encode(secret_key: &SecretKey, content: &Message) -> Result<Bytes> {
+        Self::sign_and_encode_with_certificate(secret_key, None, content)
+    }
+
+    pub fn sign_and_encode_with_certificate(
+        secret_key: &SecretKey,
+        certificate: Option<&DeviceCertificate>,
+        content: &Message,
+    ) -> Result<Bytes> {
+        Self::sign_and_encode_with_opts(secret_key, certificate, content, Durability::Durable, None)
+    }
+
+    pub fn sign_and_encode_with_opts(
+        secret_key: &SecretKey,
+        certificate: Option<&DeviceCertificate>,
+        content: &Message,
+        durability: Durability,
+        ttl: Option<Duration>,
+    ) -> Result<Bytes> {
         let data: Bytes = postcard::to_stdvec(&content)?.into();
-        let signature = secret_key.sign(&data);
+        let sent_at = now_millis();
+        let ttl = ttl.map(|ttl| ttl.as_millis() as u64);
+        let signature = secret_key.sign(&signed_bytes(&data, sent_at, ttl, durability)?);
         let from: PublicKey = secret_key.public();
         let signed_message = Self {
             from,
             data,
+            sent_at,
+            ttl,
+            durability,
             signature,
+            certificate: certificate.cloned(),
         };
         let encoded = postcard::to_stdvec(&signed_message)?;
         Ok(encoded.into())
     }
 }
+
+// The signature covers the metadata too, so nobody can extend the life of a message.
+fn signed_bytes(data: &Bytes, sent_at: u64, ttl: Option<u64>, durability: Durability) -> Result<Vec<u8>> {
+    Ok(postcard::to_stdvec(&(data, sent_at, ttl, durability))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::signed_message::tests::attribute_to_root -- --exact --nocapture'
+    fn attribute_to_root() -> Result<()> {
+        let root = SecretKey::generate(rand::rngs::OsRng);
+        let laptop = SecretKey::generate(rand::rngs::OsRng);
+        let workstation = SecretKey::generate(rand::rngs::OsRng);
+        let message = Message::text("hello");
+
+        let laptop_cert = DeviceCertificate::create(&root, laptop.public());
+        let bytes = SignedMessage::sign_and_encode_with_certificate(&laptop, Some(&laptop_cert), &message)?;
+        let (author, device, decoded) = SignedMessage::verify_and_decode_with_device(&bytes)?;
+        assert_eq!(author, root.public());
+        assert_eq!(device, laptop.public());
+        assert_eq!(decoded, message);
+
+        let workstation_cert = DeviceCertificate::create(&root, workstation.public());
+        let bytes = SignedMessage::sign_and_encode_with_certificate(&workstation, Some(&workstation_cert), &message)?;
+        let (author, _) = SignedMessage::verify_and_decode(&bytes)?;
+        assert_eq!(author, root.public());
+
+        // A certificate issued to another device is rejected.
+        let bytes = SignedMessage::sign_and_encode_with_certificate(&workstation, Some(&laptop_cert), &message)?;
+        assert!(SignedMessage::verify_and_decode(&bytes).is_err());
+
+        let bytes = SignedMessage::sign_and_encode(&laptop, &message)?;
+        let (author, _) = SignedMessage::verify_and_decode(&bytes)?;
+        assert_eq!(author, laptop.public());
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::signed_message::tests::expiry_is_signed -- --exact --nocapture'
+    fn expiry_is_signed() -> Result<()> {
+        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+        let message = Message::text("typing...");
+        let bytes = SignedMessage::sign_and_encode_with_opts(
+            &secret_key,
+            None,
+            &message,
+            Durability::Ephemeral,
+            Some(Duration::ZERO),
+        )?;
+        std::thread::sleep(Duration::from_millis(5));
+        let envelope = SignedMessage::verify_and_decode_envelope(&bytes)?;
+        assert!(envelope.is_ephemeral());
+        assert!(envelope.is_expired());
+
+        // Extending the ttl invalidates the signature.
+        let mut signed_message: SignedMessage = postcard::from_bytes(&bytes)?;
+        signed_message.ttl = Some(u64::MAX);
+        let tampered = postcard::to_stdvec(&signed_message)?;
+        assert!(SignedMessage::verify_and_decode_envelope(&tampered).is_err());
+
+        let bytes = SignedMessage::sign_and_encode(&secret_key, &message)?;
+        let envelope = SignedMessage::verify_and_decode_envelope(&bytes)?;
+        assert!(!envelope.is_ephemeral());
+        assert!(!envelope.is_expired());
+        Ok(())
+    }
+
+    fn sign_with_times(secret_key: &SecretKey, sent_at: u64, ttl: Option<u64>) -> Result<Vec<u8>> {
+        let data: Bytes = postcard::to_stdvec(&Message::text("far future"))?.into();
+        let signature = secret_key.sign(&signed_bytes(&data, sent_at, ttl, Durability::Ephemeral)?);
+        let signed_message = SignedMessage {
+            from: secret_key.public(),
+            data,
+            sent_at,
+            ttl,
+            durability: Durability::Ephemeral,
+            signature,
+            certificate: None,
+        };
+        Ok(postcard::to_stdvec(&signed_message)?)
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::signed_message::tests::times_out_of_range -- --exact --nocapture'
+    fn times_out_of_range() -> Result<()> {
+        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+        // A ttl past the end of the clock never expires.
+        let bytes = sign_with_times(&secret_key, now_millis(), Some(u64::MAX))?;
+        let envelope = SignedMessage::verify_and_decode_envelope(&bytes)?;
+        assert!(!envelope.is_expired());
+
+        // A send time past the end of the clock is rejected, where the platform clock ends before it.
+        let bytes = sign_with_times(&secret_key, u64::MAX, Some(u64::MAX))?;
+        match from_millis(u64::MAX) {
+            None => assert!(SignedMessage::verify_and_decode_envelope(&bytes).is_err()),
+            Some(_) => assert!(!SignedMessage::verify_and_decode_envelope(&bytes)?.is_expired()),
+        }
+        Ok(())
+    }
+}
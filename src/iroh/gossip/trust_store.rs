@@ -0,0 +1,120 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{Error, Result};
+use iroh::PublicKey;
+use serde::{Deserialize, Serialize};
+
+use super::KeyRotation;
+
+// Local record of accepted key rotations.
+// Every key is resolved to the first key of its chain, so apps can keep
+// using that one for rosters, allowlists and names.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrustStore {
+    successors: HashMap<PublicKey, PublicKey>,
+    predecessors: HashMap<PublicKey, PublicKey>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        TrustStore::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(TrustStore::new());
+        }
+        let bytes = std::fs::read(path)?;
+        Ok(postcard::from_bytes(&bytes)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let bytes = postcard::to_stdvec(self)?;
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, rotation: &KeyRotation) -> Result<()> {
+        rotation.verify()?;
+        let (old, new) = (rotation.old_key(), rotation.new_key());
+        match self.successors.get(&old) {
+            Some(successor) if *successor == new => return Ok(()),
+            Some(_) => return Err(Error::Rejected { at: "trust_store::record", reason: "key already rotated" }),
+            None => {}
+        }
+        if self.is_known(&new) {
+            return Err(Error::Rejected { at: "trust_store::record", reason: "new key already known" });
+        }
+        self.successors.insert(old, new);
+        self.predecessors.insert(new, old);
+        Ok(())
+    }
+
+    pub fn resolve(&self, key: &PublicKey) -> PublicKey {
+        let mut current = *key;
+        while let Some(previous) = self.predecessors.get(&current) {
+            current = *previous;
+        }
+        current
+    }
+
+    pub fn latest(&self, key: &PublicKey) -> PublicKey {
+        let mut current = *key;
+        while let Some(next) = self.successors.get(&current) {
+            current = *next;
+        }
+        current
+    }
+
+    pub fn is_same_person(&self, a: &PublicKey, b: &PublicKey) -> bool {
+        self.resolve(a) == self.resolve(b)
+    }
+
+    pub fn is_rotated(&self, key: &PublicKey) -> bool {
+        self.successors.contains_key(key)
+    }
+
+    fn is_known(&self, key: &PublicKey) -> bool {
+        self.successors.contains_key(key) || self.predecessors.contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::trust_store::tests::follow_rotation_chain -- --exact --nocapture'
+    fn follow_rotation_chain() -> Result<()> {
+        let k1 = SecretKey::generate(rand::rngs::OsRng);
+        let k2 = SecretKey::generate(rand::rngs::OsRng);
+        let k3 = SecretKey::generate(rand::rngs::OsRng);
+        let mut store = TrustStore::new();
+        store.record(&KeyRotation::create(&k1, &k2)?)?;
+        store.record(&KeyRotation::create(&k2, &k3)?)?;
+        assert_eq!(store.resolve(&k3.public()), k1.public());
+        assert_eq!(store.latest(&k1.public()), k3.public());
+        assert!(store.is_same_person(&k1.public(), &k3.public()));
+        assert!(store.is_rotated(&k1.public()));
+        assert!(!store.is_rotated(&k3.public()));
+        Ok(())
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::gossip::trust_store::tests::reject_forked_rotation -- --exact --nocapture'
+    fn reject_forked_rotation() -> Result<()> {
+        let k1 = SecretKey::generate(rand::rngs::OsRng);
+        let k2 = SecretKey::generate(rand::rngs::OsRng);
+        let attacker = SecretKey::generate(rand::rngs::OsRng);
+        let mut store = TrustStore::new();
+        store.record(&KeyRotation::create(&k1, &k2)?)?;
+        // Recording the same statement twice is fine.
+        store.record(&KeyRotation::create(&k1, &k2)?)?;
+        // Someone with the leaked old key cannot fork the identity.
+        assert!(store.record(&KeyRotation::create(&k1, &attacker)?).is_err());
+        assert_eq!(store.latest(&k1.public()), k2.public());
+        Ok(())
+    }
+}
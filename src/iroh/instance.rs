@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use anyhow::{Result, anyhow};
+use crate::{Error, Result};
 use iroh::{
     Endpoint, NodeAddr, NodeId, PublicKey, RelayUrl, SecretKey,
     endpoint::{DirectAddrInfo, RemoteInfo},
@@ -17,9 +17,8 @@ use super::IrohData;
 pub enum IrohInstance<T> {
     Empty,
     Data {
-        iroh_data: IrohData,
+        iroh_data: Box<IrohData>,
         data: T,
-        debug: bool,
     },
 }
 
@@ -33,7 +32,7 @@ impl<T> IrohInstance<T> {
             IrohInstance::Empty => {}
             IrohInstance::Data { iroh_data, .. } => {
                 iroh_data.endpoint.close().await;
-                iroh_data.router.shutdown().await?;
+                iroh_data.router.shutdown().await.map_err(Error::Router)?;
             }
         }
         Ok(())
@@ -46,9 +45,10 @@ impl<T> IrohInstance<T> {
         let relay = self.relay_url();
         match endpoint_relay == relay {
             true => Ok(()),
-            false => Err(anyhow!(
-                "instance::assert_correct_relay::EndpointHasDifferentRelay"
-            )),
+            false => Err(Error::RelayMismatch {
+                endpoint: endpoint_relay,
+                expected: relay,
+            }),
         }
     }
 }
@@ -82,7 +82,7 @@ impl<T> IrohInstance<T> {
         match self {
             IrohInstance::Empty => Ok(None),
             IrohInstance::Data { iroh_data, .. } => {
-                let node_addr = iroh_data.endpoint.node_addr().await?;
+                let node_addr = iroh_data.endpoint.node_addr().await.map_err(Error::NodeAddr)?;
                 Ok(Some(node_addr))
             }
         }
@@ -99,9 +99,9 @@ impl<T> IrohInstance<T> {
 
     pub fn add_node_addr(&self, node_addr: NodeAddr) -> Result<()> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::add_node_addr::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::add_node_addr")),
             IrohInstance::Data { iroh_data, .. } => {
-                iroh_data.endpoint.add_node_addr(node_addr)?;
+                iroh_data.endpoint.add_node_addr(node_addr).map_err(Error::NodeAddr)?;
             }
         }
         Ok(())
@@ -109,7 +109,7 @@ impl<T> IrohInstance<T> {
 
     pub fn subscribe(&self, node_ids: Vec<NodeId>) -> Result<GossipTopic> {
         match self {
-            IrohInstance::Empty => Err(anyhow!("instance::subscribe::UserIsEmpty")),
+            IrohInstance::Empty => Err(Error::EmptyInstance("instance::subscribe")),
             IrohInstance::Data { iroh_data, .. } => {
                 Ok(iroh_data.gossip.subscribe(iroh_data.topic_id, node_ids)?)
             }
@@ -118,7 +118,7 @@ impl<T> IrohInstance<T> {
 
     pub async fn subscribe_and_join(&self, node_ids: Vec<NodeId>) -> Result<GossipTopic> {
         match self {
-            IrohInstance::Empty => Err(anyhow!("instance::subscribe_and_join::UserIsEmpty")),
+            IrohInstance::Empty => Err(Error::EmptyInstance("instance::subscribe_and_join")),
             IrohInstance::Data { iroh_data, .. } => Ok(iroh_data
                 .gossip
                 .subscribe_and_join(iroh_data.topic_id, node_ids)
@@ -128,14 +128,14 @@ impl<T> IrohInstance<T> {
 
     pub fn remote_info_iter(&self) -> Result<impl Iterator<Item = RemoteInfo>> {
         match self {
-            IrohInstance::Empty => Err(anyhow!("instance::peers_addrs::UserIsEmpty")),
+            IrohInstance::Empty => Err(Error::EmptyInstance("instance::peers_addrs")),
             IrohInstance::Data { iroh_data, .. } => Ok(iroh_data.endpoint.remote_info_iter()),
         }
     }
 
     pub fn online_peers(&self) -> Result<HashMap<NodeId, Vec<DirectAddrInfo>>> {
         match self {
-            IrohInstance::Empty => Err(anyhow!("instance::peers_addrs::UserIsEmpty")),
+            IrohInstance::Empty => Err(Error::EmptyInstance("instance::peers_addrs")),
             IrohInstance::Data { .. } => {
                 let mut hmap: HashMap<NodeId, Vec<DirectAddrInfo>> = HashMap::new();
                 for info in self.remote_info_iter()? {
@@ -151,7 +151,7 @@ impl<T> IrohInstance<T> {
 
     pub fn offline_peers(&self) -> Result<Vec<NodeId>> {
         match self {
-            IrohInstance::Empty => Err(anyhow!("instance::peers_addrs::UserIsEmpty")),
+            IrohInstance::Empty => Err(Error::EmptyInstance("instance::peers_addrs")),
             IrohInstance::Data { .. } => {
                 let mut nodeid_vec: Vec<NodeId> = Vec::new();
                 for info in self.remote_info_iter()? {
@@ -171,7 +171,7 @@ impl<T> IrohInstance<T> {
     pub fn iroh_data(&self) -> Option<IrohData> {
         match self {
             IrohInstance::Empty => None,
-            IrohInstance::Data { iroh_data, .. } => Some(iroh_data.clone()),
+            IrohInstance::Data { iroh_data, .. } => Some(iroh_data.as_ref().clone()),
         }
     }
 
@@ -207,20 +207,13 @@ impl<T> IrohInstance<T> {
         // Get the RelayUrl currently in the IrohData struct //TODO: transform in docs
         match self {
             IrohInstance::Empty => None,
-            IrohInstance::Data { iroh_data, .. } => Some(iroh_data.relay_url.clone()),
-        }
-    }
-
-    pub fn debug(&self) -> bool {
-        match self {
-            IrohInstance::Empty => false,
-            IrohInstance::Data { debug, .. } => *debug,
+            IrohInstance::Data { iroh_data, .. } => iroh_data.relay_url.clone(),
         }
     }
 
     pub fn set_endpoint(&mut self, endpoint: Endpoint) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_endpoint::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::set_endpoint")),
             IrohInstance::Data { iroh_data, .. } => {
                 iroh_data.endpoint = endpoint;
             }
@@ -230,7 +223,7 @@ impl<T> IrohInstance<T> {
 
     pub fn set_gossip(&mut self, gossip: Gossip) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_gossip::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::set_gossip")),
             IrohInstance::Data { iroh_data, .. } => {
                 iroh_data.gossip = gossip;
             }
@@ -240,7 +233,7 @@ impl<T> IrohInstance<T> {
 
     pub fn set_router(&mut self, router: Router) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_router::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::set_router")),
             IrohInstance::Data { iroh_data, .. } => {
                 iroh_data.router = router;
             }
@@ -250,7 +243,7 @@ impl<T> IrohInstance<T> {
 
     pub fn set_topic_id(&mut self, topic_id: TopicId) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_topic_id::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::set_topic_id")),
             IrohInstance::Data { iroh_data, .. } => {
                 iroh_data.topic_id = topic_id;
             }
@@ -260,19 +253,9 @@ impl<T> IrohInstance<T> {
 
     pub fn set_relay_url(&mut self, relay_url: RelayUrl) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_relay_url::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("instance::set_relay_url")),
             IrohInstance::Data { iroh_data, .. } => {
-                iroh_data.relay_url = relay_url;
-            }
-        }
-        Ok(self)
-    }
-
-    pub fn set_debug(&mut self, debug: bool) -> Result<&mut Self> {
-        match self {
-            IrohInstance::Empty => return Err(anyhow!("instance::set_debug::UserIsEmpty")),
-            IrohInstance::Data { debug: debug_, .. } => {
-                *debug_ = debug;
+                iroh_data.relay_url = Some(relay_url);
             }
         }
         Ok(self)
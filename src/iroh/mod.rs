@@ -1,23 +1,60 @@
+mod connect_config;
+mod connect_options_builder;
 mod connection;
+mod connection_event;
 mod data;
+mod discovery_config;
 mod generate_server_secret_key;
 mod get_server_addr;
 mod get_server_addresses;
+mod get_server_relay;
 mod instance;
+mod partition_merger;
+mod peer_cache;
+mod peer_exchange;
+mod probe;
+mod relay_config;
 mod server;
+mod server_keys;
+mod slot_claim;
+mod slot_search_error;
+mod slot_strategy;
 mod user;
 mod server_future;
+mod shutdown;
+mod supervised_connection;
 
+pub use connect_config::CONFIG_PATH_VAR;
+pub use connect_config::ConnectConfig;
+pub use connect_config::seed_from_passphrase;
+pub use connect_options_builder::ConnectOptionsBuilder;
 pub use connection::ConnectOptions;
 pub use connection::Connection;
+pub use connection_event::ConnectionEvent;
+pub use connection_event::ConnectionEventReceiver;
+pub use connection_event::ConnectionEventSender;
 pub use data::IrohData;
+pub use discovery_config::DiscoveryConfig;
 pub use generate_server_secret_key::generate_server_secret_key;
 pub use get_server_addr::get_server_addr;
 pub use get_server_addresses::get_server_addresses;
+pub use get_server_relay::get_server_relay;
 pub use instance::IrohInstance;
+pub use relay_config::RelayConfig;
+pub use relay_config::relay_map_from_urls;
+pub use peer_cache::CachedPeer;
+pub use peer_cache::PeerCache;
+pub use probe::PROBE_ALPN;
 pub use server::Server;
+pub use server_keys::ServerKeys;
+pub use slot_search_error::SlotSearchError;
+pub use slot_search_error::SlotSearchLimit;
+pub use slot_strategy::SlotChooser;
+pub use slot_strategy::SlotStrategy;
 pub use user::GossipFuture;
 pub use user::User;
 pub use server_future::ServerFuture;
+pub use shutdown::ShutdownReport;
+pub use supervised_connection::SupervisedConnection;
 
 pub mod gossip;
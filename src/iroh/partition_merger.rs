@@ -0,0 +1,195 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh_gossip::net::{Event, GossipEvent};
+use n0_future::TryStreamExt;
+
+use crate::{Error, Result};
+
+use super::{
+    ConnectOptions, ConnectionEvent, User, get_server_addr::slot_server_addr, get_server_relay,
+    gossip::SignedMessage, peer_cache::SharedPeerCache, probe::{probe, read_holder}, slot_claim::HELD_TTL,
+    server_future::{ServerList, lock, lock_list},
+};
+
+// A peer joined by the merger is not joined again before this many merge intervals,
+// the gossip membership may have moved it out of the active view meanwhile.
+const REJOIN_INTERVALS: u32 = 5;
+// How many cached peers are probed on every tick, the next ones on the next tick.
+const CACHED_PEERS_PER_TICK: usize = 4;
+
+// Two groups bootstrapping at the same time on disjoint slots form two swarms on the
+// same topic. Every 'options.merge_interval' the merger probes the lowest server slots
+// and the known addresses, and joins the lowest online slot and the user holding it if
+// they are outside of our swarm, not heard on the topic lately: every member ends up
+// linked to the same rendezvous server, so the swarms converge.
+// The slots do not help when both swarms hold them, or when their holders are the same node
+// twice: a few peers of the 'PeerCache' are probed too, with the discovery of the endpoint
+// (pkarr or DHT) for the ones without a known path.
+pub(crate) struct PartitionMerger {
+    pub(crate) user: User,
+    pub(crate) relay_vec: Vec<String>,
+    pub(crate) seed: [u8; 32],
+    // The servers of this connection, they are part of our swarm already.
+    pub(crate) servers: ServerList,
+    pub(crate) peer_cache: Option<SharedPeerCache>,
+    pub(crate) options: ConnectOptions,
+}
+
+impl PartitionMerger {
+    pub(crate) async fn run(self, interval: Duration) -> Result<()> {
+        let endpoint = match self.user.endpoint() {
+            None => return Err(Error::EmptyInstance("partition_merger::run")),
+            Some(endpoint) => endpoint,
+        };
+        // A subscription of its own, the app keeps its Sender and Receiver.
+        let (gossip_sender, mut gossip_receiver) = self.user.subscribe(vec![])?.split();
+        let mut neighbours: HashSet<NodeId> = HashSet::new();
+        let mut joined: HashMap<NodeId, Instant> = HashMap::new();
+        // The nodes heard on the topic: the authors of the messages and the neighbours that
+        // delivered them. The servers of our swarm are heard through their 'SlotHeld'.
+        let mut heard: HashMap<NodeId, Instant> = HashMap::new();
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        let mut round: usize = 0;
+        loop {
+            tokio::select! {
+                event = gossip_receiver.try_next() => match event? {
+                    None => return Ok(()),
+                    Some(Event::Gossip(GossipEvent::NeighborUp(node_id))) => {
+                        neighbours.insert(node_id);
+                    }
+                    Some(Event::Gossip(GossipEvent::NeighborDown(node_id))) => {
+                        neighbours.remove(&node_id);
+                    }
+                    Some(Event::Gossip(GossipEvent::Received(msg))) => {
+                        heard.insert(msg.delivered_from, Instant::now());
+                        if let Ok(envelope) = SignedMessage::verify_and_decode_envelope(&msg.content) {
+                            heard.insert(envelope.device, Instant::now());
+                        }
+                    }
+                    Some(_) => {}
+                },
+                _ = ticker.tick() => {
+                    joined.retain(|_, at| at.elapsed() < interval * REJOIN_INTERVALS);
+                    // Not the endpoint's online peers: the probes below make any node look online.
+                    heard.retain(|_, at| at.elapsed() < HELD_TTL);
+                    let skip: HashSet<NodeId> =
+                        neighbours.iter().chain(joined.keys()).chain(heard.keys()).copied().collect();
+                    round = round.wrapping_add(1);
+                    let foreign_peers = match self.foreign_peers(&endpoint, &skip, round).await {
+                        Ok(foreign_peers) => foreign_peers,
+                        Err(e) => {
+                            tracing::warn!("partition merger could not probe the slots '{e}'");
+                            continue;
+                        }
+                    };
+                    for (server_id, node_addr) in foreign_peers {
+                        let node_id = node_addr.node_id;
+                        tracing::info!(?server_id, %node_id, "joining a peer outside of our swarm");
+                        self.options.emit(ConnectionEvent::JoiningForeignPeer { server_id, node_id });
+                        if let Err(e) = endpoint.add_node_addr(node_addr) {
+                            tracing::warn!(%node_id, "partition merger could not add the address '{e}'");
+                            continue;
+                        }
+                        if let Err(e) = gossip_sender.join_peers(vec![node_id]).await {
+                            tracing::warn!(%node_id, "partition merger could not join the peer '{e}'");
+                            continue;
+                        }
+                        joined.insert(node_id, Instant::now());
+                    }
+                }
+            }
+        }
+    }
+
+    // The lowest online slot below our own ones, the online known addresses and the online
+    // cached peers of this 'round', leaving out the peers of our swarm and the peers joined recently.
+    async fn foreign_peers(
+        &self,
+        endpoint: &Endpoint,
+        skip: &HashSet<NodeId>,
+        round: usize,
+    ) -> Result<Vec<(Option<u64>, NodeAddr)>> {
+        let (own_ids, own_node_ids): (Vec<u64>, HashSet<NodeId>) = {
+            let servers = lock_list(&self.servers, "partition_merger::foreign_peers")?;
+            servers.iter().filter_map(|server| Some((server.id()?, server.node_id()?))).unzip()
+        };
+        let end_id = own_ids
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(u64::MAX)
+            .min(self.options.merge_probe_slots);
+        let is_foreign = |node_id: &NodeId| {
+            *node_id != endpoint.node_id() && !own_node_ids.contains(node_id) && !skip.contains(node_id)
+        };
+
+        let mut slots: Vec<(u64, NodeAddr)> = Vec::new();
+        for id in 0..end_id {
+            let relay_url = get_server_relay(id, &self.relay_vec)?;
+            slots.push((id, slot_server_addr(id, relay_url, &self.seed)));
+        }
+        let timeout = self.options.probe_timeout;
+        let online = join_all(slots.iter().map(|(_, addr)| probe(endpoint, addr.clone(), timeout))).await;
+        // Everybody joins the lowest one, a foreign swarm is found through it.
+        let lowest = slots
+            .into_iter()
+            .zip(online)
+            .find(|(_, online)| *online)
+            .filter(|((_, addr), _)| is_foreign(&addr.node_id))
+            .map(|(slot, _)| slot);
+        let mut peers: Vec<(Option<u64>, NodeAddr)> = Vec::new();
+        if let Some((id, addr)) = lowest {
+            // Its holder too: iroh-gossip never dials a peer again once a dial failed, and the
+            // users bootstrapping with us dialed the slot before anybody held it.
+            if let Some(holder) = read_holder(endpoint, addr.clone(), timeout).await
+                && is_foreign(&holder.node_id)
+            {
+                peers.push((Some(id), holder));
+            }
+            peers.push((Some(id), addr));
+        }
+
+        let mut known: Vec<NodeAddr> = self
+            .options
+            .known_addresses
+            .iter()
+            .filter(|addr| is_foreign(&addr.node_id))
+            .cloned()
+            .collect();
+        known.extend(self.cached_peers(round, |node_id| {
+            is_foreign(node_id) && !self.options.known_addresses.iter().any(|known| known.node_id == *node_id)
+        })?);
+        let online = join_all(known.iter().map(|addr| probe(endpoint, addr.clone(), timeout))).await;
+        peers.extend(
+            known
+                .into_iter()
+                .zip(online)
+                .filter(|(_, online)| *online)
+                .map(|(addr, _)| (None, addr)),
+        );
+        Ok(peers)
+    }
+
+    // A window of the cached peers that pass 'keep', moved on every round so all of them
+    // are probed in turn.
+    fn cached_peers(&self, round: usize, keep: impl Fn(&NodeId) -> bool) -> Result<Vec<NodeAddr>> {
+        let Some(peer_cache) = &self.peer_cache else {
+            return Ok(Vec::new());
+        };
+        let cached: Vec<NodeAddr> = lock(peer_cache, "partition_merger::cached_peers")?
+            .node_addrs()
+            .into_iter()
+            .filter(|addr| keep(&addr.node_id))
+            .collect();
+        if cached.is_empty() {
+            return Ok(cached);
+        }
+        let start = round.wrapping_mul(CACHED_PEERS_PER_TICK) % cached.len();
+        Ok(cached.iter().cycle().skip(start).take(CACHED_PEERS_PER_TICK.min(cached.len())).cloned().collect())
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use iroh::{NodeAddr, NodeId, RelayUrl};
+use iroh_gossip::proto::TopicId;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+use super::{Server, User, server_future::{ServerList, lock_list}};
+
+// Enough peers to find the swarm again, few enough to leave room for the server slots
+// in the search, see 'create_user_join_handle'.
+const MAX_CACHED_PEERS: usize = 16;
+// A peer not seen online for a week is dropped when the cache is loaded.
+const MAX_PEER_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub(crate) type SharedPeerCache = Arc<Mutex<PeerCache>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPeer {
+    pub node_addr: NodeAddr,
+    // Seconds since the unix epoch, the last time the peer was seen online.
+    pub last_success: u64,
+}
+
+// The peers of a topic recently seen online, kept in a TOML file between runs.
+// 'Connection::create_with_user' searches them with the known addresses, so a reconnecting
+// user usually finds the swarm through them, before any server slot is online.
+#[derive(Debug, Clone)]
+pub struct PeerCache {
+    path: PathBuf,
+    topic_id: TopicId,
+    peers: BTreeMap<NodeId, CachedPeer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    topic: String,
+    #[serde(default)]
+    peers: Vec<PeerEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerEntry {
+    node_id: String,
+    relay_url: Option<String>,
+    #[serde(default)]
+    direct_addresses: Vec<SocketAddr>,
+    last_success: u64,
+}
+
+impl PeerCache {
+    // A missing or unreadable file gives an empty cache: it only speeds up the bootstrap.
+    pub fn load(path: impl AsRef<Path>, topic_id: TopicId) -> Self {
+        let mut cache = PeerCache {
+            path: path.as_ref().to_path_buf(),
+            topic_id,
+            peers: BTreeMap::new(),
+        };
+        if let Err(e) = cache.read() {
+            tracing::warn!(path = %cache.path.display(), "peer cache ignored '{e}'");
+        }
+        cache
+    }
+
+    fn read(&mut self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let file: CacheFile = toml::from_str(&std::fs::read_to_string(&self.path)?)?;
+        // The file of another topic is replaced on the next save.
+        if file.topic != hex::encode(self.topic_id.as_bytes()) {
+            return Ok(());
+        }
+        let oldest = unix_now().saturating_sub(MAX_PEER_AGE.as_secs());
+        for entry in file.peers.into_iter().filter(|entry| entry.last_success >= oldest) {
+            let Ok(node_id) = NodeId::from_str(&entry.node_id) else {
+                continue;
+            };
+            let relay_url = entry.relay_url.and_then(|url| RelayUrl::from_str(&url).ok());
+            let node_addr = NodeAddr::from_parts(node_id, relay_url, entry.direct_addresses);
+            let last_success = entry.last_success;
+            self.peers.insert(node_id, CachedPeer { node_addr, last_success });
+        }
+        Ok(())
+    }
+
+    // Written to a temporary file first, a crash never leaves half a cache.
+    pub fn save(&self) -> Result<()> {
+        let file = CacheFile {
+            topic: hex::encode(self.topic_id.as_bytes()),
+            peers: self
+                .peers()
+                .into_iter()
+                .map(|peer| PeerEntry {
+                    node_id: peer.node_addr.node_id.to_string(),
+                    relay_url: peer.node_addr.relay_url.map(|url| url.to_string()),
+                    direct_addresses: peer.node_addr.direct_addresses.into_iter().collect(),
+                    last_success: peer.last_success,
+                })
+                .collect(),
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, toml::to_string(&file)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    // The most recently seen first.
+    pub fn peers(&self) -> Vec<CachedPeer> {
+        let mut peers: Vec<CachedPeer> = self.peers.values().cloned().collect();
+        peers.sort_by_key(|peer| std::cmp::Reverse(peer.last_success));
+        peers.truncate(MAX_CACHED_PEERS);
+        peers
+    }
+
+    pub fn node_addrs(&self) -> Vec<NodeAddr> {
+        self.peers().into_iter().map(|peer| peer.node_addr).collect()
+    }
+
+    pub fn record(&mut self, node_addr: NodeAddr, last_success: u64) {
+        if node_addr.is_empty() {
+            return;
+        }
+        self.peers.insert(node_addr.node_id, CachedPeer { node_addr, last_success });
+    }
+
+    // Every peer the user has a working path to, but the ones in 'exclude'.
+    pub fn record_online(&mut self, user: &User, exclude: &HashSet<NodeId>) -> Result<()> {
+        let now = unix_now();
+        for info in user.remote_info_iter()? {
+            if info.latency.is_none() || exclude.contains(&info.node_id) {
+                continue;
+            }
+            self.record(info.into(), now);
+        }
+        Ok(())
+    }
+}
+
+// Saves the online peers every 'interval', the servers of the connection are left out:
+// they are gone when it reconnects.
+pub(crate) async fn keep_peer_cache(
+    cache: SharedPeerCache,
+    user: User,
+    servers: ServerList,
+    interval: Duration,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = save_online_peers(&cache, &user, &servers) {
+            tracing::warn!("could not save the peer cache '{e}'");
+        }
+    }
+}
+
+pub(crate) fn save_online_peers(cache: &SharedPeerCache, user: &User, servers: &ServerList) -> Result<()> {
+    let own_servers: HashSet<NodeId> = lock_list(servers, "peer_cache::save_online_peers")?
+        .iter()
+        .filter_map(Server::node_id)
+        .collect();
+    let mut cache = cache.lock().map_err(|_| Error::LockPoisoned("peer_cache::save_online_peers"))?;
+    cache.record_online(user, &own_servers)?;
+    cache.save()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    fn node_addr(port: u16) -> NodeAddr {
+        let node_id = SecretKey::generate(rand::rngs::OsRng).public();
+        NodeAddr::new(node_id).with_direct_addresses([SocketAddr::from(([127, 0, 0, 1], port))])
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::peer_cache::tests::saved_and_loaded -- --exact --nocapture'
+    fn saved_and_loaded() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("lele-peer-cache-{}", rand::random::<u64>()));
+        let path = dir.join("peers.toml");
+        let topic_id = TopicId::from_bytes(rand::random());
+        let now = unix_now();
+
+        let mut cache = PeerCache::load(&path, topic_id);
+        assert!(cache.peers().is_empty());
+        let (recent, older) = (node_addr(1), node_addr(2));
+        cache.record(older.clone(), now - 60);
+        cache.record(recent.clone(), now);
+        // Without any address it can not be reached.
+        cache.record(NodeAddr::new(SecretKey::generate(rand::rngs::OsRng).public()), now);
+        for port in 10..10 + MAX_CACHED_PEERS as u16 {
+            cache.record(node_addr(port), now - 3600);
+        }
+        cache.save()?;
+
+        let loaded = PeerCache::load(&path, topic_id);
+        let node_addrs = loaded.node_addrs();
+        assert_eq!(node_addrs.len(), MAX_CACHED_PEERS);
+        assert_eq!(node_addrs[..2], [recent.clone(), older]);
+
+        let mut cache = PeerCache::load(&path, topic_id);
+        cache.peers.clear();
+        cache.record(recent.clone(), now);
+        cache.record(node_addr(3), now - MAX_PEER_AGE.as_secs() - 1);
+        cache.save()?;
+        assert_eq!(PeerCache::load(&path, topic_id).node_addrs(), [recent]);
+        // Another topic does not use the peers of this one.
+        assert!(PeerCache::load(&path, TopicId::from_bytes(rand::random())).peers().is_empty());
+        // Neither does a broken file stop the connection.
+        std::fs::write(&path, "peers = 3")?;
+        assert!(PeerCache::load(&path, topic_id).peers().is_empty());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
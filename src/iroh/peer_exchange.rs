@@ -0,0 +1,221 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use iroh::{NodeAddr, NodeId, RelayMap, SecretKey};
+use iroh_gossip::net::{Event, GossipEvent, GossipSender};
+use n0_future::TryStreamExt;
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::{Error, Result};
+
+use super::{
+    ConnectOptions, ConnectionEvent, User,
+    gossip::{Durability, Message, SignedMessage},
+};
+
+// The most peers in a PEX message, bigger messages are dropped.
+pub(crate) const MAX_PEX_PEERS: usize = 8;
+// The direct addresses kept for every received peer.
+const MAX_DIRECT_ADDRESSES: usize = 8;
+// Received peers are added to the endpoint until this many are known through PEX.
+const MAX_LEARNT_PEERS: usize = 256;
+// A learnt peer counts against 'MAX_LEARNT_PEERS' for this many intervals.
+const LEARNT_INTERVALS: u32 = 10;
+// The PEX messages a neighbour may deliver in an interval, its own and the ones it relays.
+const MAX_DELIVERED_PER_NEIGHBOUR: usize = 4;
+const PEX_TTL: Duration = Duration::from_secs(60);
+// The stream of 'ConnectOptions::rng' drawing the shared samples.
+const PEX_RNG: u64 = 2;
+
+// Peer exchange: every 'options.pex_interval' the user shares a random sample of the peers
+// it has a working path to, and adds the ones shared by the others to its endpoint, so the
+// mesh keeps finding itself when the server slots are offline.
+pub(crate) struct PeerExchange {
+    pub(crate) user: User,
+    pub(crate) options: ConnectOptions,
+}
+
+impl PeerExchange {
+    pub(crate) async fn run(self, interval: Duration) -> Result<()> {
+        let (secret_key, endpoint) = match (self.user.secret_key()?, self.user.endpoint()) {
+            (Some(secret_key), Some(endpoint)) => (secret_key, endpoint),
+            _ => return Err(Error::EmptyInstance("peer_exchange::run")),
+        };
+        // Only the relays we use ourselves are accepted.
+        let relay_map = match self.user.iroh_data() {
+            Some(iroh_data) => iroh_data.relay_mode.relay_map(),
+            None => RelayMap::empty(),
+        };
+        // A subscription of its own, the app keeps its Sender and Receiver.
+        let (gossip_sender, mut gossip_receiver) = self.user.subscribe(vec![])?.split();
+        let mut delivered: HashMap<NodeId, (Instant, usize)> = HashMap::new();
+        let mut learnt: HashMap<NodeId, Instant> = HashMap::new();
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        let mut rng = self.options.rng(PEX_RNG);
+        loop {
+            tokio::select! {
+                event = gossip_receiver.try_next() => match event? {
+                    None => return Ok(()),
+                    Some(Event::Gossip(GossipEvent::Received(msg))) => {
+                        let Ok(envelope) = SignedMessage::verify_and_decode_envelope(&msg.content) else {
+                            continue;
+                        };
+                        let Message::PeerExchange { peers } = envelope.message else {
+                            continue;
+                        };
+                        let from = envelope.device;
+                        // Counted for the neighbour that delivered it: a device key costs nothing,
+                        // a gossip connection does.
+                        let (window_start, n_delivered) =
+                            delivered.entry(msg.delivered_from).or_insert((Instant::now(), 0));
+                        if window_start.elapsed() >= interval {
+                            (*window_start, *n_delivered) = (Instant::now(), 0);
+                        }
+                        if *n_delivered >= MAX_DELIVERED_PER_NEIGHBOUR {
+                            let delivered_from = msg.delivered_from;
+                            tracing::debug!(%from, %delivered_from, "dropped a peer exchange over the limit");
+                            continue;
+                        }
+                        *n_delivered += 1;
+                        let mut n_peers = 0;
+                        for node_addr in sanitize_peers(peers, endpoint.node_id(), &relay_map) {
+                            let node_id = node_addr.node_id;
+                            if learnt.len() >= MAX_LEARNT_PEERS && !learnt.contains_key(&node_id) {
+                                continue;
+                            }
+                            match endpoint.add_node_addr(node_addr) {
+                                Ok(()) => n_peers += 1,
+                                Err(e) => tracing::debug!(%node_id, "exchanged peer gave error '{e}'"),
+                            }
+                            learnt.insert(node_id, Instant::now());
+                        }
+                        tracing::trace!(%from, n_peers, "peers exchanged");
+                        self.options.emit(ConnectionEvent::PeersExchanged { from, n_peers });
+                    }
+                    Some(_) => {}
+                },
+                _ = ticker.tick() => {
+                    delivered.retain(|_, (window_start, _)| window_start.elapsed() < interval);
+                    learnt.retain(|_, at| at.elapsed() < interval * LEARNT_INTERVALS);
+                    if let Err(e) = self.share_peers(&secret_key, &gossip_sender, &mut rng).await {
+                        tracing::warn!("could not share the online peers '{e}'");
+                    }
+                }
+            }
+        }
+    }
+
+    async fn share_peers(&self, secret_key: &SecretKey, gossip_sender: &GossipSender, rng: &mut StdRng) -> Result<()> {
+        let peers = sample_online_peers(&self.user, rng)?;
+        if peers.is_empty() {
+            return Ok(());
+        }
+        let bytes = SignedMessage::sign_and_encode_with_opts(
+            secret_key,
+            None,
+            &Message::peer_exchange(peers),
+            Durability::Ephemeral,
+            Some(PEX_TTL),
+        )?;
+        gossip_sender.broadcast(bytes).await?;
+        Ok(())
+    }
+}
+
+// A random sample of the peers the user has a working path to.
+fn sample_online_peers(user: &User, rng: &mut StdRng) -> Result<Vec<NodeAddr>> {
+    let mut peers: Vec<NodeAddr> = user
+        .remote_info_iter()?
+        .filter(|info| info.latency.is_some())
+        .map(NodeAddr::from)
+        .filter(|node_addr| !node_addr.is_empty())
+        .collect();
+    // Sorted first, the sample only depends on the rng and the online peers.
+    peers.sort_by_key(|node_addr| node_addr.node_id);
+    peers.shuffle(rng);
+    peers.truncate(MAX_PEX_PEERS);
+    Ok(peers)
+}
+
+// Leaves out what no honest peer sends: too many peers or addresses, ourselves,
+// addresses nobody can be reached at and relays outside of our relay map. Loopback
+// addresses are only kept without relays, when all the nodes run on the local network.
+pub(crate) fn sanitize_peers(peers: Vec<NodeAddr>, own_node_id: NodeId, relay_map: &RelayMap) -> Vec<NodeAddr> {
+    if peers.len() > MAX_PEX_PEERS {
+        return Vec::new();
+    }
+    let lan_only = relay_map.is_empty();
+    let mut seen: HashSet<NodeId> = HashSet::new();
+    peers
+        .into_iter()
+        .filter(|node_addr| node_addr.node_id != own_node_id && seen.insert(node_addr.node_id))
+        .filter_map(|node_addr| {
+            let relay_url = node_addr.relay_url.filter(|url| relay_map.contains_node(url));
+            let direct_addresses: Vec<SocketAddr> = node_addr
+                .direct_addresses
+                .into_iter()
+                .filter(|addr| is_reachable(addr, lan_only))
+                .take(MAX_DIRECT_ADDRESSES)
+                .collect();
+            let node_addr = NodeAddr::from_parts(node_addr.node_id, relay_url, direct_addresses);
+            (!node_addr.is_empty()).then_some(node_addr)
+        })
+        .collect()
+}
+
+fn is_reachable(addr: &SocketAddr, lan_only: bool) -> bool {
+    if addr.ip().is_loopback() && !lan_only {
+        return false;
+    }
+    let usable_ip = match addr.ip() {
+        IpAddr::V4(ip) => !ip.is_unspecified() && !ip.is_multicast() && !ip.is_broadcast(),
+        IpAddr::V6(ip) => !ip.is_unspecified() && !ip.is_multicast(),
+    };
+    usable_ip && addr.port() != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::RelayUrl;
+    use std::str::FromStr;
+
+    fn node_id() -> NodeId {
+        SecretKey::generate(rand::rngs::OsRng).public()
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::peer_exchange::tests::sanity_limits -- --exact --nocapture'
+    fn sanity_limits() -> Result<()> {
+        let own_node_id = node_id();
+        let our_relay = RelayUrl::from_str("https://relay.example./")?;
+        let other_relay = RelayUrl::from_str("https://elsewhere.example./")?;
+        let relay_map = RelayMap::from_url(our_relay.clone());
+        let good = NodeAddr::new(node_id())
+            .with_relay_url(our_relay)
+            .with_direct_addresses(["192.168.1.2:11204".parse().unwrap()]);
+        let unreachable = NodeAddr::new(node_id())
+            .with_relay_url(other_relay)
+            .with_direct_addresses(["0.0.0.0:11204".parse().unwrap(), "224.0.0.1:5".parse().unwrap()]);
+        let many_addresses =
+            NodeAddr::new(node_id()).with_direct_addresses((1..=20).map(|port| SocketAddr::from(([10, 0, 0, 1], port))));
+        let peers = vec![good.clone(), good.clone(), NodeAddr::new(own_node_id), unreachable, many_addresses];
+
+        let sanitized = sanitize_peers(peers, own_node_id, &relay_map);
+        assert_eq!(sanitized.len(), 2);
+        assert_eq!(sanitized[0], good);
+        assert_eq!(sanitized[1].direct_addresses.len(), MAX_DIRECT_ADDRESSES);
+
+        // Loopback is only reachable when nobody uses a relay.
+        let loopback = NodeAddr::new(node_id()).with_direct_addresses(["127.0.0.1:11204".parse().unwrap()]);
+        assert!(sanitize_peers(vec![loopback.clone()], own_node_id, &relay_map).is_empty());
+        assert_eq!(sanitize_peers(vec![loopback.clone()], own_node_id, &RelayMap::empty()), vec![loopback]);
+
+        let too_many: Vec<NodeAddr> = (0..=MAX_PEX_PEERS).map(|_| good.clone()).collect();
+        assert!(sanitize_peers(too_many, own_node_id, &relay_map).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use iroh::{
+    Endpoint, NodeAddr, NodeId, RelayMap, RelayMode, RelayUrl, endpoint::Connection, protocol::ProtocolHandler,
+};
+use n0_future::boxed::BoxFuture;
+
+use super::discovery_config::transport_config;
+use crate::{Error, Result};
+
+// Probes have their own ALPN: a probe on the gossip ALPN is taken by iroh-gossip as the
+// connection of that peer, and the real join of the same peer is then ignored.
+pub const PROBE_ALPN: &[u8] = b"lele/probe/0";
+
+// The most a holder answer is read, a 'NodeAddr' with a few direct addresses.
+const MAX_HOLDER_LEN: usize = 1024;
+
+// Answers probes, every user and server endpoint accepts them.
+// A server also tells the user holding its slot, see 'probe_holder'.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProbeHandler {
+    holder: Option<NodeAddr>,
+}
+
+impl ProbeHandler {
+    pub(crate) fn holding(holder: NodeAddr) -> Self {
+        ProbeHandler { holder: Some(holder) }
+    }
+}
+
+impl ProtocolHandler for ProbeHandler {
+    fn accept(&self, connection: Connection) -> BoxFuture<anyhow::Result<()>> {
+        let holder = self.holder.as_ref().and_then(|holder| postcard::to_stdvec(holder).ok());
+        Box::pin(async move {
+            // A plain probe closes the connection right away, then there is nobody to tell.
+            if let Some(holder) = holder
+                && let Ok(mut send) = connection.open_uni().await
+                && send.write_all(&holder).await.is_ok()
+            {
+                let _ = send.finish();
+            }
+            connection.closed().await;
+            Ok(())
+        })
+    }
+}
+
+// True if the node answers a probe within 'timeout'.
+pub(crate) async fn probe(endpoint: &Endpoint, node_addr: NodeAddr, timeout: Duration) -> bool {
+    let connecting = endpoint.connect(node_addr, PROBE_ALPN);
+    match tokio::time::timeout(timeout, connecting).await {
+        Ok(Ok(connection)) => {
+            connection.close(0u32.into(), b"probe");
+            true
+        }
+        _ => false,
+    }
+}
+
+// The user holding the server slot 'node_id' on 'relay_url', if it answers within 'timeout'.
+// Two users holding the same slot share its node id, and the relay forwards to one of them:
+// the last one to register, they take turns as their relay connections are replaced.
+// A throwaway endpoint goes through the relay every time, the user endpoint would keep
+// reaching the holder it found first over a direct path. Local network discovery is left
+// out for the same reason.
+pub(crate) async fn probe_holder(relay_url: RelayUrl, node_id: NodeId, timeout: Duration) -> Result<Option<NodeAddr>> {
+    let endpoint = Endpoint::builder()
+        .relay_mode(RelayMode::Custom(RelayMap::from_url(relay_url.clone())))
+        .transport_config(transport_config())
+        .bind()
+        .await
+        .map_err(Error::Bind)?;
+    let holder = read_holder(&endpoint, NodeAddr::new(node_id).with_relay_url(relay_url), timeout).await;
+    // Closing waits for the probe connection to drain, the next probe does not.
+    tokio::spawn(async move { endpoint.close().await });
+    Ok(holder)
+}
+
+// The user holding the slot of the server at 'node_addr', as told by its 'ProbeHandler'.
+pub(crate) async fn read_holder(endpoint: &Endpoint, node_addr: NodeAddr, timeout: Duration) -> Option<NodeAddr> {
+    tokio::time::timeout(timeout, async {
+        let connection = endpoint.connect(node_addr, PROBE_ALPN).await.ok()?;
+        let holder = match connection.accept_uni().await {
+            Ok(mut recv) => recv.read_to_end(MAX_HOLDER_LEN).await.ok(),
+            Err(_) => None,
+        };
+        connection.close(0u32.into(), b"probe");
+        postcard::from_bytes(&holder?).ok()
+    })
+    .await
+    .unwrap_or_default()
+}
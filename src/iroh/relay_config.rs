@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use crate::{Error, Result};
+use iroh::{RelayMap, RelayMode, RelayUrl};
+
+// Which relays the endpoints created by lele are allowed to use.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RelayConfig {
+    // Only the relays given to 'Connection::create', like 'consts::RELAY_VEC'.
+    #[default]
+    RelayList,
+    // A custom relay map, the server slots are spread across its relays.
+    Custom(RelayMap),
+    // iroh's default relays (run by n0).
+    N0,
+    // LAN-only mode, for air-gapped networks: no relay is used, the peers and the
+    // server slots are found through local network discovery and direct addresses.
+    Disabled,
+}
+
+impl RelayConfig {
+    // The relays on which the server slots live.
+    pub fn relay_vec(&self, relays_str: &[&str]) -> Vec<String> {
+        match self {
+            RelayConfig::RelayList | RelayConfig::N0 => {
+                relays_str.iter().map(|s| s.to_string()).collect()
+            }
+            RelayConfig::Custom(relay_map) => relay_map.urls().map(|url| url.to_string()).collect(),
+            RelayConfig::Disabled => Vec::new(),
+        }
+    }
+
+    pub fn relay_mode(&self, relays_str: &[&str]) -> Result<RelayMode> {
+        match self {
+            RelayConfig::RelayList => {
+                let mut relay_urls: Vec<RelayUrl> = Vec::new();
+                for relay_str in relays_str {
+                    relay_urls.push(RelayUrl::from_str(relay_str)?);
+                }
+                Ok(RelayMode::Custom(relay_map_from_urls(&relay_urls)?))
+            }
+            RelayConfig::Custom(relay_map) => Ok(RelayMode::Custom(relay_map.clone())),
+            RelayConfig::N0 => Ok(RelayMode::Default),
+            RelayConfig::Disabled => Ok(RelayMode::Disabled),
+        }
+    }
+}
+
+pub fn relay_map_from_urls(relay_urls: &[RelayUrl]) -> Result<RelayMap> {
+    let nodes: Vec<_> = relay_urls
+        .iter()
+        .flat_map(|url| RelayMap::from_url(url.clone()).nodes().cloned().collect::<Vec<_>>())
+        .collect();
+    RelayMap::from_nodes(nodes).map_err(Error::RelayMap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::RELAY_VEC;
+
+    #[test]
+    // run test by using: 'cargo test iroh::relay_config::tests::relay_list_is_used -- --exact --nocapture'
+    fn relay_list_is_used() -> Result<()> {
+        let relays = ["https://relay-a.example.com./", "https://relay-b.example.com./"];
+        let RelayMode::Custom(relay_map) = RelayConfig::RelayList.relay_mode(&relays)? else {
+            panic!("RelayList should give a custom relay map");
+        };
+        assert_eq!(relay_map.len(), 2);
+        let custom = RelayConfig::Custom(relay_map);
+        assert_eq!(custom.relay_vec(RELAY_VEC), relays.to_vec());
+        assert_eq!(RelayConfig::N0.relay_mode(&relays)?, RelayMode::Default);
+        assert!(RelayConfig::Disabled.relay_vec(RELAY_VEC).is_empty());
+        Ok(())
+    }
+}
@@ -1,6 +1,13 @@
-use super::{IrohData, IrohInstance, generate_server_secret_key};
-use anyhow::Result;
-use iroh::{Endpoint, RelayUrl, protocol::Router};
+use std::time::Duration;
+
+use super::{
+    DiscoveryConfig, IrohData, IrohInstance, ServerKeys, generate_server_secret_key,
+    gossip::{Durability, Message, SignedMessage},
+    probe::{PROBE_ALPN, ProbeHandler},
+    slot_claim::HELD_TTL,
+};
+use crate::{Error, Result};
+use iroh::{Endpoint, NodeAddr, NodeId, RelayMap, RelayMode, RelayUrl, protocol::Router};
 use iroh_gossip::{net::Gossip, proto::TopicId};
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -10,38 +17,108 @@ pub struct ServerData {
 
 pub type Server = IrohInstance<ServerData>;
 
+const RELEASE_TTL: Duration = Duration::from_secs(30);
+
 // create and close methods
 impl Server {
+    // 'seed' is the seed of the swarm, the slot seed of 'topic_id' is derived from it.
     pub async fn create(
         id: u64,
         topic_id: TopicId,
-        relay_url: RelayUrl,
+        relay_url: Option<RelayUrl>,
         seed: &[u8; 32],
+        server_keys: ServerKeys,
+        discovery: DiscoveryConfig,
+    ) -> Result<Self> {
+        let slot_seed = server_keys.slot_seed(seed, &topic_id);
+        Server::create_for_slot(id, topic_id, relay_url, &slot_seed, discovery, None).await
+    }
+
+    // 'slot_seed' is derived already, see 'ServerKeys::slot_seed'.
+    // The probes of the slot are answered with 'holder', see 'probe_holder'.
+    pub(crate) async fn create_for_slot(
+        id: u64,
+        topic_id: TopicId,
+        relay_url: Option<RelayUrl>,
+        slot_seed: &[u8; 32],
+        discovery: DiscoveryConfig,
+        holder: Option<NodeAddr>,
     ) -> Result<Self> {
-        let secret_key = generate_server_secret_key(id, seed);
-        let endpoint = Endpoint::builder().secret_key(secret_key).bind().await?;
+        let secret_key = generate_server_secret_key(id, slot_seed);
+        // The server is pinned to 'relay_url', that is where users look for it.
+        // Without a relay it is only reachable on the local network.
+        let relay_mode = match &relay_url {
+            None => RelayMode::Disabled,
+            Some(relay_url) => RelayMode::Custom(RelayMap::from_url(relay_url.clone())),
+        };
+        let builder = Endpoint::builder()
+            .secret_key(secret_key)
+            .relay_mode(relay_mode.clone());
+        let endpoint = discovery.apply(builder).bind().await.map_err(Error::Bind)?;
+        tracing::debug!(server_id = id, node_id = %endpoint.node_id(), topic = %topic_id, "server endpoint bound");
         let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
         let router = Router::builder(endpoint.clone())
             .accept(iroh_gossip::ALPN, gossip.clone())
+            .accept(PROBE_ALPN, holder.map(ProbeHandler::holding).unwrap_or_default())
             .spawn()
-            .await?;
+            .await
+            .map_err(Error::Router)?;
         let iroh_data = IrohData {
             endpoint,
             gossip,
             router,
             topic_id,
             relay_url,
+            relay_mode,
+            discovery,
         };
         let data = ServerData { id };
-        Ok(Server::Data {
-            iroh_data,
-            data,
-            debug: false,
-        })
+        Ok(Server::Data { iroh_data: Box::new(iroh_data), data })
     }
 }
 
 impl Server {
+    // Tells the topic that this slot is about to go offline, so a neighbour takes it over
+    // right away. Signed by the server key, nobody else can release the slot.
+    pub async fn release_slot(&self) -> Result<()> {
+        let (id, secret_key) = match (self.id(), self.secret_key()?) {
+            (Some(id), Some(secret_key)) => (id, secret_key),
+            _ => return Err(Error::EmptyInstance("server::release_slot")),
+        };
+        let (gossip_sender, _) = self.subscribe(vec![])?.split();
+        let bytes = SignedMessage::sign_and_encode_with_opts(
+            &secret_key,
+            None,
+            &Message::slot_release(id),
+            Durability::Ephemeral,
+            Some(RELEASE_TTL),
+        )?;
+        gossip_sender.broadcast(bytes).await?;
+        tracing::debug!(server_id = id, "slot released");
+        // Leave the message the time to reach the neighbours before closing.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        Ok(())
+    }
+
+    // Tells the topic that the user 'holder' hosts this slot, see 'SlotClaims::should_yield'.
+    // Signed by the server key like a release, so a peer without it cannot take the slot over.
+    pub(crate) async fn announce_held(&self, holder: NodeId) -> Result<()> {
+        let (id, secret_key) = match (self.id(), self.secret_key()?) {
+            (Some(id), Some(secret_key)) => (id, secret_key),
+            _ => return Err(Error::EmptyInstance("server::announce_held")),
+        };
+        let (gossip_sender, _) = self.subscribe(vec![])?.split();
+        let bytes = SignedMessage::sign_and_encode_with_opts(
+            &secret_key,
+            None,
+            &Message::slot_held(id, holder),
+            Durability::Ephemeral,
+            Some(HELD_TTL),
+        )?;
+        gossip_sender.broadcast(bytes).await?;
+        Ok(())
+    }
+
     pub fn id(&self) -> Option<u64> {
         match self {
             IrohInstance::Empty => None,
@@ -53,22 +130,26 @@ impl Server {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use anyhow::Result;
     use crate::{
-        consts::{RELAY_VEC, SEED, TOPIC},
+        consts::{SEED, TOPIC},
         iroh::Server,
+        tests::local_relay::LocalRelay,
     };
     use std::str::FromStr;
 
     #[tokio::test]
     // run test by using: 'cargo test iroh::server::tests::same_args_same_server -- --exact --nocapture'
     async fn same_args_same_server() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
         let topic_id = TopicId::from_str(TOPIC)?;
-        let relay_url = RelayUrl::from_str(RELAY_VEC[0])?;
+        let relay_url = Some(relay.relay_url());
         let id_1 = 0;
         let id_2 = 1;
-        let server1 = Server::create(id_1, topic_id, relay_url.clone(), &SEED).await?;
-        let server1_copy = Server::create(id_1, topic_id, relay_url.clone(), &SEED).await?;
-        let server2 = Server::create(id_2, topic_id, relay_url.clone(), &SEED).await?;
+        let (keys, discovery) = (ServerKeys::default(), DiscoveryConfig::default());
+        let server1 = Server::create(id_1, topic_id, relay_url.clone(), &SEED, keys, discovery.clone()).await?;
+        let server1_copy = Server::create(id_1, topic_id, relay_url.clone(), &SEED, keys, discovery.clone()).await?;
+        let server2 = Server::create(id_2, topic_id, relay_url.clone(), &SEED, keys, discovery.clone()).await?;
         println!("server1.public_key()?: {:?}", server1.public_key()?);
         println!("server2.public_key()?: {:?}", server2.public_key()?);
 
@@ -78,6 +159,10 @@ mod tests {
             server1_copy.endpoint_relay().await?
         );
         assert_ne!(server1.public_key()?, server2.public_key()?);
+        server1.close().await?;
+        server1_copy.close().await?;
+        server2.close().await?;
+        relay.shutdown().await?;
         Ok(())
     }
 }
@@ -1,20 +1,183 @@
-use anyhow::Result;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
-use super::Server;
+use futures::future::join_all;
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, timeout_at},
+};
+
+use crate::{Error, Result};
+
+use super::{
+    Server, ShutdownReport, User,
+    peer_cache::{SharedPeerCache, save_online_peers},
+};
+
+// 'ServerFuture::close' has no timeout of its own, this one keeps it from hanging.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Every server endpoint created by a connection: its own slot, the slots it moved
+// away from and the ones it adopted. They are all closed with the connection.
+pub(crate) type ServerList = Arc<Mutex<Vec<Server>>>;
+// The background loops of a connection: one for every server and the partition merger.
+pub(crate) type LoopTasks = Arc<Mutex<Vec<JoinHandle<Result<()>>>>>;
+
+// Locks a 'ServerList' or the 'LoopTasks', a task that panicked while holding it is an error.
+pub(crate) fn lock_list<'a, T>(list: &'a Mutex<Vec<T>>, at: &'static str) -> Result<MutexGuard<'a, Vec<T>>> {
+    lock(list, at)
+}
+
+pub(crate) fn lock<'a, T>(mutex: &'a Mutex<T>, at: &'static str) -> Result<MutexGuard<'a, T>> {
+    mutex.lock().map_err(|_| Error::LockPoisoned(at))
+}
 
 #[derive(Debug)]
 pub struct ServerFuture {
-    pub handle: tokio::task::JoinHandle<anyhow::Result<Server>>,
+    pub handle: JoinHandle<Result<Server>>,
+    pub(crate) servers: ServerList,
+    pub(crate) loops: LoopTasks,
+    pub(crate) adopter: Option<JoinHandle<Result<()>>>,
+    pub(crate) peer_cache: Option<SharedPeerCache>,
 }
 
 impl ServerFuture {
-    pub async fn close(self) -> Result<()> {
-        if self.handle.is_finished() {
-            let server = self.handle.await??;
-            server.close().await?;
-        } else  {
-            self.handle.abort();
+    pub fn new(handle: JoinHandle<Result<Server>>) -> Self {
+        ServerFuture::with_parts(handle, Default::default(), Default::default())
+    }
+
+    pub(crate) fn with_parts(handle: JoinHandle<Result<Server>>, servers: ServerList, loops: LoopTasks) -> Self {
+        ServerFuture {
+            handle,
+            servers,
+            loops,
+            adopter: None,
+            peer_cache: None,
+        }
+    }
+
+    // Every hosted slot is released first, so the neighbours take them over right away.
+    pub async fn close(mut self) -> Result<()> {
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        let mut report = ShutdownReport::default();
+        self.release_slots(deadline, &mut report).await;
+        self.stop_loops(deadline, &mut report).await;
+        self.close_servers(deadline, &mut report).await;
+        match report.unfinished.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Shutdown(report.unfinished)),
+        }
+    }
+
+    // Like 'Connection::shutdown', for apps that took the connection apart.
+    // The gossip topic is left once the app dropped its Sender and Receiver.
+    pub async fn shutdown(mut self, user: User, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+        self.save_peer_cache(&user, &mut report);
+        self.release_slots(deadline, &mut report).await;
+        self.stop_loops(deadline, &mut report).await;
+        self.close_with_user(user, deadline, &mut report).await;
+        report
+    }
+
+    // The peers online right before leaving, the next connection starts from them.
+    pub(crate) fn save_peer_cache(&self, user: &User, report: &mut ShutdownReport) {
+        if let Some(peer_cache) = &self.peer_cache
+            && let Err(e) = save_online_peers(peer_cache, user, &self.servers)
+        {
+            report.unfinished.push(format!("peer cache: {e}"));
         }
-        Ok(())
     }
-}
\ No newline at end of file
+
+    // Stops the slot adopter and the server start, then releases every hosted slot
+    // while the server loops still keep the servers in the topic.
+    pub(crate) async fn release_slots(&mut self, deadline: Instant, report: &mut ShutdownReport) {
+        if let Some(adopter) = self.adopter.take() {
+            stop_task(adopter, "slot adopter", deadline, report).await;
+        }
+        match self.handle.is_finished() {
+            true => match (&mut self.handle).await {
+                Ok(Err(e)) => report.unfinished.push(format!("server start: {e}")),
+                Err(e) => report.unfinished.push(format!("server start: {e}")),
+                Ok(Ok(_)) => {}
+            },
+            false => {
+                // The endpoints it created so far are in the server list.
+                self.handle.abort();
+                report.stopped_tasks += 1;
+            }
+        }
+        let servers: Vec<Server> = match lock_list(&self.servers, "server_future::release_slots") {
+            Ok(servers) => servers.clone(),
+            Err(e) => {
+                report.unfinished.push(e.to_string());
+                return;
+            }
+        };
+        for server in servers {
+            let id = server.id().unwrap_or_default();
+            match timeout_at(deadline, server.release_slot()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!(server_id = id, "could not release the slot '{e}'"),
+                Err(_) => report.unfinished.push(format!("server {id}: release timed out")),
+            }
+        }
+    }
+
+    // The server loops hold the last subscriptions of the servers, they leave the topic here.
+    pub(crate) async fn stop_loops(&self, deadline: Instant, report: &mut ShutdownReport) {
+        let loops: Vec<_> = match lock_list(&self.loops, "server_future::stop_loops") {
+            Ok(mut loops) => loops.drain(..).collect(),
+            Err(e) => {
+                report.unfinished.push(e.to_string());
+                return;
+            }
+        };
+        for handle in loops {
+            stop_task(handle, "server loop", deadline, report).await;
+        }
+    }
+
+    // Closed together: every endpoint waits up to a few seconds for its peers to acknowledge.
+    pub(crate) async fn close_servers(self, deadline: Instant, report: &mut ShutdownReport) {
+        let servers: Vec<Server> = match lock_list(&self.servers, "server_future::close_servers") {
+            Ok(mut servers) => servers.drain(..).collect(),
+            Err(e) => {
+                report.unfinished.push(e.to_string());
+                return;
+            }
+        };
+        let closing = servers.into_iter().map(|server| async move {
+            let id = server.id().unwrap_or_default();
+            (id, timeout_at(deadline, server.close()).await)
+        });
+        for (id, closed) in join_all(closing).await {
+            match closed {
+                Ok(Ok(())) => report.closed_servers.push(id),
+                Ok(Err(e)) => report.unfinished.push(format!("server {id}: {e}")),
+                Err(_) => report.unfinished.push(format!("server {id}: timed out")),
+            }
+        }
+    }
+
+    // The servers are closed first, then the user endpoint, see 'Connection::shutdown'.
+    pub(crate) async fn close_with_user(self, user: User, deadline: Instant, report: &mut ShutdownReport) {
+        self.close_servers(deadline, report).await;
+        match timeout_at(deadline, user.close()).await {
+            Ok(Ok(())) => report.user_closed = true,
+            Ok(Err(e)) => report.unfinished.push(format!("user: {e}")),
+            Err(_) => report.unfinished.push("user: timed out".to_string()),
+        }
+    }
+}
+
+async fn stop_task(handle: JoinHandle<Result<()>>, name: &str, deadline: Instant, report: &mut ShutdownReport) {
+    handle.abort();
+    match timeout_at(deadline, handle).await {
+        Ok(_) => report.stopped_tasks += 1,
+        Err(_) => report.unfinished.push(format!("{name}: timed out")),
+    }
+}
@@ -0,0 +1,52 @@
+use iroh_gossip::proto::TopicId;
+
+// How the keys, and so the addresses, of the server slots are derived from the seed.
+// Every node of a swarm must use the same scheme, or they do not find each other's servers:
+// 'SeedOnly' stays the default so the running deployments keep working, 'PerTopic' is opt-in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerKeys {
+    // From the seed, the topic and the slot id: topics sharing a seed have separate slots.
+    // Recommended for new deployments, all of their nodes must opt in.
+    PerTopic,
+    // From the seed and the slot id only, like every release before 'PerTopic':
+    // topics sharing a seed compete for the same slots.
+    #[default]
+    SeedOnly,
+}
+
+impl ServerKeys {
+    // The seed given to 'generate_server_secret_key' for the slots of 'topic_id'.
+    pub fn slot_seed(&self, seed: &[u8; 32], topic_id: &TopicId) -> [u8; 32] {
+        match self {
+            ServerKeys::PerTopic => topic_slot_seed(seed, topic_id),
+            ServerKeys::SeedOnly => *seed,
+        }
+    }
+}
+
+pub(crate) fn topic_slot_seed(seed: &[u8; 32], topic_id: &TopicId) -> [u8; 32] {
+    blake3::keyed_hash(seed, topic_id.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iroh::{get_server_addr, get_server_addr::slot_server_addr};
+
+    #[test]
+    // run test by using: 'cargo test iroh::server_keys::tests::slots_per_topic -- --exact --nocapture'
+    fn slots_per_topic() {
+        let seed: [u8; 32] = rand::random();
+        let (topic_a, topic_b) = (TopicId::from_bytes(rand::random()), TopicId::from_bytes(rand::random()));
+        let slot_addr = |keys: ServerKeys, topic_id: &TopicId| get_server_addr(0, None, &seed, topic_id, keys);
+
+        assert_ne!(slot_addr(ServerKeys::PerTopic, &topic_a), slot_addr(ServerKeys::PerTopic, &topic_b));
+        assert_eq!(slot_addr(ServerKeys::PerTopic, &topic_a), slot_addr(ServerKeys::PerTopic, &topic_a));
+        let topic_a_seed = topic_slot_seed(&seed, &topic_a);
+        assert_eq!(slot_addr(ServerKeys::PerTopic, &topic_a), slot_server_addr(0, None, &topic_a_seed));
+        // The old addresses are kept for the existing deployments.
+        assert_eq!(slot_addr(ServerKeys::SeedOnly, &topic_a), slot_server_addr(0, None, &seed));
+        assert_eq!(slot_addr(ServerKeys::SeedOnly, &topic_a), slot_addr(ServerKeys::SeedOnly, &topic_b));
+        assert_eq!(ServerKeys::default(), ServerKeys::SeedOnly);
+    }
+}
@@ -0,0 +1,49 @@
+use iroh::Endpoint;
+
+// What 'Connection::shutdown' did, and what it could not finish before its timeout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    // The server loops, the slot adopter and the server start that were stopped.
+    pub stopped_tasks: usize,
+    pub closed_servers: Vec<u64>,
+    pub user_closed: bool,
+    // One line for every step that failed or timed out, like "server 3: timed out".
+    pub unfinished: Vec<String>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.user_closed && self.unfinished.is_empty()
+    }
+}
+
+// Warns when a connection is dropped with its user endpoint still open: its servers,
+// loops and endpoints keep running in the background until the process exits.
+#[derive(Debug)]
+pub(crate) struct LeakGuard {
+    endpoint: Option<Endpoint>,
+}
+
+impl LeakGuard {
+    pub(crate) fn new(endpoint: Option<Endpoint>) -> Self {
+        LeakGuard { endpoint }
+    }
+
+    // The parts are closed by someone else from now on.
+    pub(crate) fn disarm(&mut self) {
+        self.endpoint = None;
+    }
+}
+
+impl Drop for LeakGuard {
+    fn drop(&mut self) {
+        if let Some(endpoint) = &self.endpoint
+            && !endpoint.is_closed()
+        {
+            tracing::warn!(
+                node_id = %endpoint.node_id(),
+                "connection dropped without 'Connection::shutdown', its endpoints and tasks are leaked"
+            );
+        }
+    }
+}
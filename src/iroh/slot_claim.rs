@@ -0,0 +1,401 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Result};
+use iroh::{Endpoint, NodeAddr, NodeId, SecretKey};
+use iroh_gossip::net::{Event, GossipEvent, GossipSender};
+use n0_future::{TryStreamExt, task::AbortOnDropHandle};
+use rand::{Rng, rngs::StdRng};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use super::{
+    ConnectOptions, User, generate_server_secret_key,
+    probe::{probe, probe_holder},
+    gossip::{Durability, Message, SignedMessage},
+    server_future::lock,
+};
+
+// How long a heard claim keeps its slot reserved.
+const CLAIM_TTL: Duration = Duration::from_secs(30);
+// A user claims one slot at a time, a few more while adopting or keeping hosted slots:
+// the claims of a peer past this limit are ignored, it cannot reserve every slot.
+const MAX_CLAIMS_PER_PEER: usize = 8;
+// How often the servers announce their slot, and how long an announcement is remembered.
+pub(crate) const HELD_INTERVAL: Duration = Duration::from_secs(5);
+pub(crate) const HELD_TTL: Duration = Duration::from_secs(15);
+// How many times a slot is probed for its holder before giving up until the next check.
+const HOLDER_PROBES: usize = 5;
+// The stream of 'ConnectOptions::rng' drawing the claim backoffs.
+pub(crate) const CLAIMS_RNG: u64 = 1;
+
+// Every slot with the users that claimed it, and when.
+type HeardClaims = HashMap<u64, Vec<(NodeId, Instant)>>;
+
+// The slots released by their servers, see 'Server::release_slot'.
+pub(crate) type SlotReleases = mpsc::UnboundedReceiver<u64>;
+
+// The claims of server slots heard on the topic.
+// Before binding the endpoint of slot N a user probes it, waits a random backoff and
+// announces its claim; when several users claim N at the same time the lowest NodeId
+// takes it, so each slot has a single holder.
+// Users bootstrapping alone at the same time do not hear each other's claims and may
+// both bind N: once the swarms meet through the announcements of their servers, or a probe
+// of N through its relay reaches the other holder, the higher NodeId yields.
+#[derive(Debug, Clone)]
+pub(crate) struct SlotClaims {
+    secret_key: SecretKey,
+    endpoint: Endpoint,
+    gossip_sender: GossipSender,
+    heard: Arc<Mutex<HeardClaims>>,
+    hosted: Arc<Mutex<HashMap<u64, Instant>>>,
+    // The users holding a slot, as announced by its server, see 'Message::SlotHeld'.
+    held: Arc<Mutex<HeardClaims>>,
+    // The slots our user is claiming right now.
+    claiming: Arc<Mutex<HashSet<u64>>>,
+    rng: Arc<Mutex<StdRng>>,
+    // The listener stops, and its subscription leaves the topic, with the last clone.
+    _listener: Arc<AbortOnDropHandle<()>>,
+}
+
+impl SlotClaims {
+    // Listens to the topic of 'user' with its own subscription, the app receiver is untouched.
+    pub(crate) fn listen(user: &User, seed: &[u8; 32], rng: StdRng) -> Result<(Self, SlotReleases)> {
+        let (secret_key, endpoint) = match (user.secret_key()?, user.endpoint()) {
+            (Some(secret_key), Some(endpoint)) => (secret_key, endpoint),
+            _ => return Err(Error::EmptyInstance("slot_claim::listen")),
+        };
+        let (gossip_sender, mut gossip_receiver) = user.subscribe(vec![])?.split();
+        let heard: Arc<Mutex<HeardClaims>> = Default::default();
+        let hosted: Arc<Mutex<HashMap<u64, Instant>>> = Default::default();
+        let held: Arc<Mutex<HeardClaims>> = Default::default();
+        let (heard_clone, hosted_clone, held_clone) = (heard.clone(), hosted.clone(), held.clone());
+        let (releases_tx, releases) = mpsc::unbounded_channel();
+        let seed = *seed;
+        let span = tracing::debug_span!("slot_claims", node_id = %endpoint.node_id());
+        let listener = tokio::spawn(
+            async move {
+                while let Ok(Some(event)) = gossip_receiver.try_next().await {
+                    let Event::Gossip(GossipEvent::Received(msg)) = event else {
+                        continue;
+                    };
+                    let Ok(envelope) = SignedMessage::verify_and_decode_envelope(&msg.content) else {
+                        continue;
+                    };
+                    match envelope.message {
+                        Message::SlotClaim { slot } => {
+                            let Ok(mut heard) = lock(&heard_clone, "slot_claim::listen") else {
+                                tracing::warn!(slot, "dropped slot claim, the claims are poisoned");
+                                continue;
+                            };
+                            let n_claims = heard
+                                .iter()
+                                .filter(|(claimed, _)| **claimed != slot)
+                                .filter(|(_, claims)| {
+                                    claims.iter().any(|(claimant, at)| *claimant == envelope.device && at.elapsed() < CLAIM_TTL)
+                                })
+                                .count();
+                            if n_claims >= MAX_CLAIMS_PER_PEER {
+                                tracing::debug!(slot, claimant = %envelope.device, "dropped claim past the limit");
+                                continue;
+                            }
+                            tracing::debug!(slot, claimant = %envelope.device, "heard slot claim");
+                            heard.entry(slot).or_default().push((envelope.device, Instant::now()));
+                        }
+                        Message::SlotRelease { slot } => {
+                            if envelope.device != generate_server_secret_key(slot, &seed).public() {
+                                tracing::debug!(slot, device = %envelope.device, "dropped release from another key");
+                                continue;
+                            }
+                            tracing::debug!(slot, "heard slot release");
+                            let at = "slot_claim::listen";
+                            for removed in [
+                                lock(&heard_clone, at).map(|mut heard| heard.remove(&slot).is_some()),
+                                lock(&hosted_clone, at).map(|mut hosted| hosted.remove(&slot).is_some()),
+                                lock(&held_clone, at).map(|mut held| held.remove(&slot).is_some()),
+                            ] {
+                                if let Err(e) = removed {
+                                    tracing::warn!(slot, "could not forget the released slot: {e}");
+                                }
+                            }
+                            let _ = releases_tx.send(slot);
+                        }
+                        Message::SlotHeld { slot, holder } => {
+                            if envelope.device != generate_server_secret_key(slot, &seed).public() {
+                                tracing::debug!(slot, device = %envelope.device, "dropped held slot from another key");
+                                continue;
+                            }
+                            // Announced again and again, only the last one of every holder is kept.
+                            let Ok(mut held) = lock(&held_clone, "slot_claim::listen") else {
+                                tracing::warn!(slot, "dropped held slot, the held slots are poisoned");
+                                continue;
+                            };
+                            let holders = held.entry(slot).or_default();
+                            holders.retain(|(known, at)| *known != holder && at.elapsed() < HELD_TTL);
+                            holders.push((holder, Instant::now()));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            .instrument(span),
+        );
+        let claims = SlotClaims {
+            secret_key,
+            endpoint,
+            gossip_sender,
+            heard,
+            hosted,
+            held,
+            claiming: Default::default(),
+            rng: Arc::new(Mutex::new(rng)),
+            _listener: Arc::new(AbortOnDropHandle::new(listener)),
+        };
+        Ok((claims, releases))
+    }
+
+    // The slot answered a probe, or another user recently claimed it.
+    pub(crate) fn is_taken(&self, slot: u64) -> Result<bool> {
+        let hosted = {
+            let mut hosted = lock(&self.hosted, "slot_claim::is_taken")?;
+            hosted.retain(|_, at| at.elapsed() < CLAIM_TTL);
+            hosted.contains_key(&slot)
+        };
+        Ok(hosted || self.is_claimed_by_other(slot)?)
+    }
+
+    // Another user recently claimed 'slot' and wins over us.
+    fn is_claimed_by_other(&self, slot: u64) -> Result<bool> {
+        let node_id = self.secret_key.public();
+        let mut heard = lock(&self.heard, "slot_claim::is_claimed_by_other")?;
+        let Some(claims) = heard.get_mut(&slot) else {
+            return Ok(false);
+        };
+        claims.retain(|(_, at)| at.elapsed() < CLAIM_TTL);
+        Ok(claims.iter().any(|(claimant, _)| *claimant < node_id))
+    }
+
+    // Returns true if the slot is ours to bind.
+    pub(crate) async fn claim(&self, slot: u64, server_addr: NodeAddr, options: &ConnectOptions) -> Result<bool> {
+        // The bootstrap and the slot adopter may go for the same slot, our own claims never
+        // compete with each other: the second one loses right away.
+        if !lock(&self.claiming, "slot_claim::claim")?.insert(slot) {
+            tracing::debug!(slot, "slot is already being claimed by this user");
+            return Ok(false);
+        }
+        // Also when the claim is dropped half way.
+        let _claiming = ClaimingGuard { claiming: &self.claiming, slot };
+        self.try_claim(slot, server_addr, options).await
+    }
+
+    async fn try_claim(&self, slot: u64, server_addr: NodeAddr, options: &ConnectOptions) -> Result<bool> {
+        if self.is_online(slot, server_addr.clone(), options).await? {
+            tracing::debug!(slot, "slot is already hosted");
+            return Ok(false);
+        }
+        let backoff = options.claim_backoff.mul_f64(lock(&self.rng, "slot_claim::try_claim")?.r#gen::<f64>());
+        tokio::time::sleep(backoff).await;
+        if self.is_claimed_by_other(slot)? {
+            tracing::debug!(slot, "slot was claimed during the backoff");
+            return Ok(false);
+        }
+        let message = Message::slot_claim(slot);
+        let bytes = SignedMessage::sign_and_encode_with_opts(
+            &self.secret_key,
+            None,
+            &message,
+            Durability::Ephemeral,
+            Some(CLAIM_TTL),
+        )?;
+        self.gossip_sender.broadcast(bytes).await?;
+        tokio::time::sleep(options.claim_window).await;
+        if self.is_claimed_by_other(slot)? {
+            tracing::debug!(slot, "lost the slot claim");
+            return Ok(false);
+        }
+        // Someone who did not hear our claim may have bound it meanwhile.
+        Ok(!self.is_online(slot, server_addr, options).await?)
+    }
+
+    // Another user holds 'slot' too and wins over us like in a claim: its server announced
+    // it, and the slot answers a probe with its key. Otherwise the announcement is dropped.
+    pub(crate) async fn should_yield(&self, slot: u64, server_addr: NodeAddr, options: &ConnectOptions) -> Result<bool> {
+        if !self.is_held_by_other(slot)? {
+            return Ok(false);
+        }
+        if self.is_online(slot, server_addr, options).await? {
+            return Ok(true);
+        }
+        tracing::debug!(slot, "dropped held slot that does not answer");
+        lock(&self.held, "slot_claim::should_yield")?.remove(&slot);
+        Ok(false)
+    }
+
+    fn is_held_by_other(&self, slot: u64) -> Result<bool> {
+        let node_id = self.secret_key.public();
+        let mut held = lock(&self.held, "slot_claim::is_held_by_other")?;
+        let Some(holders) = held.get_mut(&slot) else {
+            return Ok(false);
+        };
+        holders.retain(|(_, at)| at.elapsed() < HELD_TTL);
+        Ok(holders.iter().any(|(holder, _)| *holder < node_id))
+    }
+
+    // The other user holding the slot too, when its server is reached through the relay.
+    // The relay connections of two holders keep replacing each other, so their probes often
+    // get no answer: after a failed probe the slot is probed again a few times, also when it
+    // answers for us.
+    // Without a relay the slot cannot be told apart from our own server.
+    pub(crate) async fn other_holder(&self, server_addr: &NodeAddr, options: &ConnectOptions) -> Result<Option<NodeAddr>> {
+        let Some(relay_url) = server_addr.relay_url.clone() else {
+            return Ok(None);
+        };
+        let node_id = self.secret_key.public();
+        let mut failed = false;
+        for _ in 0..HOLDER_PROBES {
+            match probe_holder(relay_url.clone(), server_addr.node_id, options.probe_timeout).await? {
+                Some(holder) if holder.node_id != node_id => return Ok(Some(holder)),
+                Some(_) if !failed => return Ok(None),
+                Some(_) => continue,
+                None => failed = true,
+            }
+        }
+        Ok(None)
+    }
+
+    // Joins 'node_addr' on the topic, with the subscription of the claims.
+    pub(crate) async fn join_peer(&self, node_addr: NodeAddr) -> Result<()> {
+        let node_id = node_addr.node_id;
+        self.endpoint.add_node_addr(node_addr).map_err(Error::NodeAddr)?;
+        self.gossip_sender.join_peers(vec![node_id]).await?;
+        Ok(())
+    }
+
+    async fn is_online(&self, slot: u64, server_addr: NodeAddr, options: &ConnectOptions) -> Result<bool> {
+        let online = probe(&self.endpoint, server_addr, options.probe_timeout).await;
+        if online {
+            lock(&self.hosted, "slot_claim::is_online")?.insert(slot, Instant::now());
+        }
+        Ok(online)
+    }
+}
+
+struct ClaimingGuard<'a> {
+    claiming: &'a Mutex<HashSet<u64>>,
+    slot: u64,
+}
+
+impl Drop for ClaimingGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut claiming) = self.claiming.lock() {
+            claiming.remove(&self.slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use iroh_gossip::proto::TopicId;
+
+    use super::*;
+    use crate::{iroh::get_server_addr::slot_server_addr, tests::local_relay::{LocalRelay, shutdown_all}};
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::slot_claim::tests::single_holder -- --exact --nocapture'
+    async fn single_holder() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let connections = relay.spawn_users(2, topic_id, &seed).await?;
+        let options = relay.options();
+        let slot = 42;
+        let server_addr = slot_server_addr(slot, Some(relay.relay_url()), &seed);
+
+        let (first, _) = SlotClaims::listen(&connections[0].user, &seed, ConnectOptions::default().rng(CLAIMS_RNG))?;
+        let (second, _) = SlotClaims::listen(&connections[1].user, &seed, ConnectOptions::default().rng(CLAIMS_RNG))?;
+        let (first_won, second_won) = tokio::join!(
+            first.claim(slot, server_addr.clone(), &options),
+            second.claim(slot, server_addr, &options),
+        );
+        assert!(first_won? ^ second_won?);
+
+        shutdown_all(connections).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::slot_claim::tests::own_claims -- --exact --nocapture'
+    async fn own_claims() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let connections = relay.spawn_users(1, topic_id, &seed).await?;
+        let options = relay.options();
+        let slot = 42;
+        let server_addr = slot_server_addr(slot, Some(relay.relay_url()), &seed);
+
+        let (claims, _) = SlotClaims::listen(&connections[0].user, &seed, ConnectOptions::default().rng(CLAIMS_RNG))?;
+        let (first_won, second_won) = tokio::join!(
+            claims.claim(slot, server_addr.clone(), &options),
+            claims.claim(slot, server_addr.clone(), &options),
+        );
+        assert!(first_won? ^ second_won?);
+        // Claimed again once the others are done.
+        assert!(claims.claim(slot, server_addr, &options).await?);
+
+        shutdown_all(connections).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // run test by using: 'cargo test iroh::slot_claim::tests::checked_announcements -- --exact --nocapture'
+    async fn checked_announcements() -> Result<()> {
+        let relay = LocalRelay::spawn().await?;
+        let topic_id = TopicId::from_bytes(rand::random());
+        let seed: [u8; 32] = rand::random();
+        let connections = relay.spawn_users(2, topic_id, &seed).await?;
+        let (claims, _) = SlotClaims::listen(&connections[0].user, &seed, ConnectOptions::default().rng(CLAIMS_RNG))?;
+        let node_id = claims.secret_key.public();
+        let holder = loop {
+            let holder = SecretKey::generate(rand::rngs::OsRng).public();
+            if holder < node_id {
+                break holder;
+            }
+        };
+        let peer_key = connections[1].user.secret_key()?.unwrap();
+        let (sender, _) = connections[1].user.subscribe(vec![])?.split();
+        let sign = |secret_key: &SecretKey, message: Message| {
+            SignedMessage::sign_and_encode_with_opts(secret_key, None, &message, Durability::Ephemeral, Some(HELD_TTL))
+        };
+
+        // Only the server of a slot announces it, and a peer only reserves a few slots.
+        sender.broadcast(sign(&peer_key, Message::slot_held(7, holder))?).await?;
+        for slot in 0..20 {
+            sender.broadcast(sign(&peer_key, Message::slot_claim(slot))?).await?;
+        }
+        sender.broadcast(sign(&generate_server_secret_key(8, &seed), Message::slot_held(8, holder))?).await?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !claims.is_held_by_other(8)? {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Ok::<_, Error>(())
+        })
+        .await??;
+        assert!(!claims.is_held_by_other(7)?);
+        assert_eq!(lock(&claims.heard, "checked_announcements")?.len(), MAX_CLAIMS_PER_PEER);
+        // Nobody answers for slot 8, the announcement is dropped.
+        let server_addr = slot_server_addr(8, Some(relay.relay_url()), &seed);
+        assert!(!claims.should_yield(8, server_addr, &relay.options()).await?);
+        assert!(!claims.is_held_by_other(8)?);
+
+        shutdown_all(connections).await?;
+        relay.shutdown().await?;
+        Ok(())
+    }
+}
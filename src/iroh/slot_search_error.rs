@@ -0,0 +1,36 @@
+use std::{fmt, time::Duration};
+
+// Which bound stopped the server-slot search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotSearchLimit {
+    MaxServerId(u64),
+    Deadline(Duration),
+}
+
+// Returned when no server slot could be taken within 'ConnectOptions::max_server_id'
+// and 'ConnectOptions::slot_search_deadline'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotSearchError {
+    pub limit: SlotSearchLimit,
+    // Slots looked at, how many of them were online and how many were claimed by others.
+    pub searched_slots: u64,
+    pub online_slots: usize,
+    pub taken_slots: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for SlotSearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = match self.limit {
+            SlotSearchLimit::MaxServerId(id) => format!("max server id {id} reached"),
+            SlotSearchLimit::Deadline(deadline) => format!("deadline of {deadline:?} reached"),
+        };
+        write!(
+            f,
+            "No free server slot: {limit} after {:?}, {} slots searched ({} online, {} taken)",
+            self.elapsed, self.searched_slots, self.online_slots, self.taken_slots
+        )
+    }
+}
+
+impl std::error::Error for SlotSearchError {}
@@ -0,0 +1,154 @@
+use std::{fmt, sync::Arc};
+
+use iroh::NodeId;
+
+// Picks a slot among 'offline_ids' (sorted, not empty) for the user 'node_id'.
+// 'previous' is the slot the user is already trying to take, if any.
+pub type SlotChooser = Arc<dyn Fn(NodeId, &[u64], Option<u64>) -> u64 + Send + Sync>;
+
+// How a user picks the server slot it is going to host.
+#[derive(Clone, Default)]
+pub enum SlotStrategy {
+    // The lowest offline slot, or a random one of the searched slots when alone.
+    // Users joining at the same time compete for the same slot.
+    #[default]
+    LowestOffline,
+    // Any offline slot, kept while it stays offline.
+    Random,
+    // The offline slot with the highest hash of (NodeId, slot), so every user
+    // has its own preferred slots (rendezvous hashing).
+    Hashed,
+    // A chooser given by the app, a slot that is not offline falls back to the lowest one.
+    Custom(SlotChooser),
+}
+
+impl fmt::Debug for SlotStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotStrategy::LowestOffline => write!(f, "LowestOffline"),
+            SlotStrategy::Random => write!(f, "Random"),
+            SlotStrategy::Hashed => write!(f, "Hashed"),
+            SlotStrategy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl SlotStrategy {
+    pub fn custom(chooser: impl Fn(NodeId, &[u64], Option<u64>) -> u64 + Send + Sync + 'static) -> Self {
+        SlotStrategy::Custom(Arc::new(chooser))
+    }
+
+    // None when 'offline_ids' is empty.
+    pub fn choose(&self, node_id: NodeId, offline_ids: &[u64], previous: Option<u64>) -> Option<u64> {
+        let lowest = *offline_ids.first()?;
+        let id = match self {
+            SlotStrategy::LowestOffline => lowest,
+            SlotStrategy::Random => match previous {
+                Some(previous) if offline_ids.contains(&previous) => previous,
+                _ => offline_ids[rand::random::<usize>() % offline_ids.len()],
+            },
+            SlotStrategy::Hashed => *offline_ids
+                .iter()
+                .max_by_key(|id| slot_hash(node_id, **id))
+                .unwrap_or(&lowest),
+            SlotStrategy::Custom(chooser) => {
+                let id = chooser(node_id, offline_ids, previous);
+                if offline_ids.contains(&id) {
+                    return Some(id);
+                }
+                tracing::warn!(id, lowest, "custom slot strategy chose a slot that is not offline");
+                lowest
+            }
+        };
+        Some(id)
+    }
+
+    // Used when no peer is found: every searched slot is free.
+    pub fn choose_alone(&self, node_id: NodeId, searched_ids: &[u64]) -> Option<u64> {
+        match self {
+            SlotStrategy::LowestOffline => SlotStrategy::Random.choose(node_id, searched_ids, None),
+            strategy => strategy.choose(node_id, searched_ids, None),
+        }
+    }
+}
+
+// The same on every platform and build, unlike 'DefaultHasher'.
+fn slot_hash(node_id: NodeId, id: u64) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(node_id.as_bytes());
+    hasher.update(&id.to_le_bytes());
+    let hash = hasher.finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash.as_bytes()[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use iroh::SecretKey;
+
+    use super::*;
+
+    // Every round the users that have no slot yet look at the same offline slots and
+    // pick one; when several users pick the same slot only one of them gets it.
+    // Returns the number of rounds needed until every user hosts a slot.
+    fn rounds_to_converge(strategy: &SlotStrategy, n_users: usize, n_slots: u64) -> usize {
+        let mut waiting: Vec<NodeId> = (0..n_users)
+            .map(|_| SecretKey::generate(rand::rngs::OsRng).public())
+            .collect();
+        let mut taken: BTreeSet<u64> = BTreeSet::new();
+        let mut previous: BTreeMap<NodeId, u64> = BTreeMap::new();
+        let mut rounds = 0;
+        while !waiting.is_empty() {
+            rounds += 1;
+            let offline_ids: Vec<u64> = (0..n_slots).filter(|id| !taken.contains(id)).collect();
+            let mut claims: BTreeMap<u64, Vec<NodeId>> = BTreeMap::new();
+            for node_id in &waiting {
+                let id = strategy.choose(*node_id, &offline_ids, previous.get(node_id).copied()).unwrap();
+                previous.insert(*node_id, id);
+                claims.entry(id).or_default().push(*node_id);
+            }
+            for (id, claimants) in claims {
+                taken.insert(id);
+                waiting.retain(|node_id| *node_id != claimants[0]);
+            }
+        }
+        rounds
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::slot_strategy::tests::convergence -- --exact --nocapture'
+    fn convergence() {
+        let (n_users, n_slots) = (20, 250);
+        let strategies = [
+            SlotStrategy::LowestOffline,
+            SlotStrategy::Random,
+            SlotStrategy::Hashed,
+            SlotStrategy::custom(|_, offline_ids, _| offline_ids[offline_ids.len() - 1]),
+        ];
+        let mut rounds = Vec::new();
+        for strategy in &strategies {
+            let n_rounds = rounds_to_converge(strategy, n_users, n_slots);
+            println!("> {strategy:?}: {n_rounds} rounds for {n_users} users");
+            rounds.push(n_rounds);
+        }
+        // Everyone competes for the same slot, one user gets in every round.
+        assert_eq!(rounds[0], n_users);
+        assert_eq!(rounds[3], n_users);
+        assert!(rounds[1] < n_users);
+        assert!(rounds[2] < n_users);
+    }
+
+    #[test]
+    // run test by using: 'cargo test iroh::slot_strategy::tests::no_offline_slot -- --exact --nocapture'
+    fn no_offline_slot() {
+        let node_id = SecretKey::generate(rand::rngs::OsRng).public();
+        for strategy in [SlotStrategy::LowestOffline, SlotStrategy::Random, SlotStrategy::Hashed] {
+            assert_eq!(strategy.choose(node_id, &[], Some(3)), None);
+            assert_eq!(strategy.choose_alone(node_id, &[]), None);
+        }
+        assert_eq!(SlotStrategy::Hashed.choose(node_id, &[7], None), Some(7));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Result;
+use iroh_gossip::{
+    net::{Event, GossipEvent, GossipReceiver},
+    proto::TopicId,
+};
+use n0_future::TryStreamExt;
+use tracing::Instrument;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{
+    ConnectOptions, Connection, ConnectionEvent, ServerFuture, User,
+    gossip::{Receiver, Sender},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const EVENTS_CAPACITY: usize = 1024;
+
+// A Connection that rejoins the swarm by itself.
+// When the user has no neighbours for 'options.reconnect_grace', the server-slot
+// search is run again with the same user; the Sender and Receiver keep working.
+#[derive(Debug)]
+pub struct SupervisedConnection {
+    user: User,
+    sender: Sender,
+    receiver: Option<Receiver>,
+    neighbours: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+    shutdown: watch::Sender<bool>,
+    supervisor: JoinHandle<Result<()>>,
+}
+
+enum Outcome {
+    Shutdown,
+    SwarmLost,
+}
+
+impl SupervisedConnection {
+    pub async fn create(topic_id: TopicId, relays_str: &[&str], seed: &[u8; 32]) -> Result<Self> {
+        SupervisedConnection::create_with_opts(topic_id, relays_str, seed, ConnectOptions::default())
+            .await
+    }
+
+    pub async fn create_with_opts(
+        topic_id: TopicId,
+        relays_str: &[&str],
+        seed: &[u8; 32],
+        options: ConnectOptions,
+    ) -> Result<Self> {
+        let connection =
+            Connection::create_with_opts(topic_id, relays_str, seed, options.clone()).await?;
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+        let sender = Sender::create(&user, gossip_sender)?;
+        let (events_tx, events_rx) = mpsc::channel(EVENTS_CAPACITY);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let neighbours = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let supervisor = Supervisor {
+            user: user.clone(),
+            sender: sender.clone(),
+            events: events_tx,
+            neighbours: neighbours.clone(),
+            dropped: dropped.clone(),
+            shutdown: shutdown_rx,
+            relay_vec: relays_str.iter().map(|s| s.to_string()).collect(),
+            seed: *seed,
+            options,
+        };
+        let span = tracing::info_span!("supervisor", topic = %topic_id, node_id = ?user.node_id());
+        let supervisor = tokio::spawn(
+            async move {
+                supervisor
+                    .run(gossip_receiver, server_future)
+                    .await
+            }
+            .instrument(span),
+        );
+        Ok(SupervisedConnection {
+            user,
+            sender,
+            receiver: Some(Receiver::from_channel(events_rx)),
+            neighbours,
+            dropped,
+            shutdown,
+            supervisor,
+        })
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    // The Receiver can be taken only once, it outlives every reconnection.
+    pub fn take_receiver(&mut self) -> Option<Receiver> {
+        self.receiver.take()
+    }
+
+    pub fn neighbours(&self) -> usize {
+        self.neighbours.load(Ordering::Relaxed)
+    }
+
+    // Events dropped because the Receiver was not read fast enough.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub async fn close(self) -> Result<()> {
+        let _ = self.shutdown.send(true);
+        self.supervisor.await??;
+        self.user.close().await?;
+        Ok(())
+    }
+}
+
+struct Supervisor {
+    user: User,
+    sender: Sender,
+    events: mpsc::Sender<Event>,
+    neighbours: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+    shutdown: watch::Receiver<bool>,
+    relay_vec: Vec<String>,
+    seed: [u8; 32],
+    options: ConnectOptions,
+}
+
+impl Supervisor {
+    async fn run(
+        mut self,
+        mut gossip_receiver: GossipReceiver,
+        mut server_future: ServerFuture,
+    ) -> Result<()> {
+        loop {
+            if let Outcome::Shutdown = self.forward_events(&mut gossip_receiver).await? {
+                return server_future.close().await;
+            }
+            tracing::info!("no neighbours, reconnecting ...");
+            self.options.emit(ConnectionEvent::Reconnecting);
+            // The old server slot may be taken by someone else by now.
+            server_future.close().await.ok();
+            let connection = match self.reconnect().await? {
+                None => return Ok(()),
+                Some(connection) => connection,
+            };
+            let (_, new_server_future, user_gossip_topic) = connection.into_parts();
+            let (gossip_sender, new_gossip_receiver) = user_gossip_topic.split();
+            self.sender.replace_gossip_sender(gossip_sender)?;
+            gossip_receiver = new_gossip_receiver;
+            server_future = new_server_future;
+            tracing::info!("reconnected!");
+        }
+    }
+
+    async fn forward_events(&mut self, gossip_receiver: &mut GossipReceiver) -> Result<Outcome> {
+        let neighbours = gossip_receiver.neighbors().count();
+        self.neighbours.store(neighbours, Ordering::Relaxed);
+        let mut empty_since: Option<Instant> = (neighbours == 0).then(Instant::now);
+        // Ticks even while events keep arriving, so the grace period is always checked.
+        let mut check = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.shutdown.changed() => return Ok(Outcome::Shutdown),
+                event = gossip_receiver.try_next() => {
+                    let event = match event {
+                        Ok(Some(event)) => event,
+                        _ => return Ok(Outcome::SwarmLost),
+                    };
+                    match &event {
+                        Event::Gossip(GossipEvent::NeighborUp(node_id)) => {
+                            self.options.emit(ConnectionEvent::NeighborUp { node_id: *node_id });
+                        }
+                        Event::Gossip(GossipEvent::NeighborDown(node_id)) => {
+                            self.options.emit(ConnectionEvent::NeighborDown { node_id: *node_id });
+                        }
+                        _ => {}
+                    }
+                    if let Event::Gossip(GossipEvent::Joined(_) | GossipEvent::NeighborUp(_) | GossipEvent::NeighborDown(_)) = &event {
+                        let neighbours = gossip_receiver.neighbors().count();
+                        self.neighbours.store(neighbours, Ordering::Relaxed);
+                        empty_since = match neighbours {
+                            0 => empty_since.or(Some(Instant::now())),
+                            _ => None,
+                        };
+                    }
+                    // The app may have dropped its Receiver or stopped reading, the connection is
+                    // still used to send: waiting here would stall the neighbour bookkeeping above,
+                    // so the events it does not keep up with are dropped and counted.
+                    forward_event(&self.events, &self.dropped, event);
+                }
+                _ = check.tick() => {
+                    if let Some(since) = empty_since
+                        && since.elapsed() >= self.options.reconnect_grace
+                    {
+                        return Ok(Outcome::SwarmLost);
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns None if a shutdown was requested while reconnecting.
+    async fn reconnect(&mut self) -> Result<Option<Connection>> {
+        self.neighbours.store(0, Ordering::Relaxed);
+        loop {
+            let relays_str: Vec<&str> = self.relay_vec.iter().map(|s| s.as_str()).collect();
+            let attempt = Connection::create_with_user(
+                self.user.clone(),
+                &relays_str,
+                &self.seed,
+                self.options.clone(),
+            );
+            tokio::select! {
+                _ = self.shutdown.changed() => return Ok(None),
+                connection = attempt => match connection {
+                    Ok(connection) => return Ok(Some(connection)),
+                    Err(e) => {
+                        tracing::warn!("reconnection failed '{e}'");
+                    }
+                },
+            }
+            tokio::select! {
+                _ = self.shutdown.changed() => return Ok(None),
+                _ = tokio::time::sleep(self.options.reconnect_grace) => {},
+            }
+        }
+    }
+}
+
+fn forward_event(events: &mpsc::Sender<Event>, dropped: &AtomicU64, event: Event) {
+    if let Err(mpsc::error::TrySendError::Full(_)) = events.try_send(event) {
+        let dropped = dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::warn!("receiver is full, dropped an event ({dropped} dropped so far)");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // run test by using: 'cargo test iroh::supervised_connection::tests::full_receiver_counts_drops -- --exact --nocapture'
+    fn full_receiver_counts_drops() -> Result<()> {
+        let (events, mut events_rx) = mpsc::channel(EVENTS_CAPACITY);
+        let dropped = AtomicU64::new(0);
+        for _ in 0..EVENTS_CAPACITY + 3 {
+            forward_event(&events, &dropped, Event::Lagged);
+        }
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        // Reading again frees room, nothing more is dropped.
+        assert!(events_rx.try_recv().is_ok());
+        forward_event(&events, &dropped, Event::Lagged);
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        // A dropped Receiver is not counted, nobody misses those events.
+        drop(events_rx);
+        forward_event(&events, &dropped, Event::Lagged);
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+}
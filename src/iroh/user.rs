@@ -1,13 +1,18 @@
-use crate::{iroh::get_server_addresses, string::random_string};
-use anyhow::{Ok, Result, anyhow};
-use iroh::{Endpoint, NodeAddr, NodeId, RelayUrl, SecretKey, protocol::Router};
+use std::{collections::HashSet, ops::Range};
+
+use crate::string::random_string;
+use crate::{Error, Result};
+use iroh::{Endpoint, NodeAddr, NodeId, RelayMode, RelayUrl, SecretKey, protocol::Router};
 use iroh_gossip::{
     net::{Gossip, GossipTopic},
     proto::TopicId,
 };
-// use tokio::task::JoinHandle;
+use tracing::Instrument;
 
-use super::{IrohData, IrohInstance};
+use super::{
+    DiscoveryConfig, IrohData, IrohInstance, ServerKeys, generate_server_secret_key,
+    probe::{PROBE_ALPN, ProbeHandler},
+};
 
 #[derive(Debug, Clone)]
 pub struct UserData {
@@ -15,129 +20,105 @@ pub struct UserData {
 }
 
 pub type User = IrohInstance<UserData>;
-pub type GossipFuture = tokio::task::JoinHandle<anyhow::Result<GossipTopic>>;
+pub type GossipFuture = tokio::task::JoinHandle<Result<GossipTopic>>;
 
 impl User {
     pub async fn create(
         secret_key: SecretKey,
         topic_id: TopicId,
-        relay_url: RelayUrl,
+        relay_url: Option<RelayUrl>,
         name: &str,
+        relay_mode: RelayMode,
+        discovery: DiscoveryConfig,
     ) -> Result<Self> {
-        // Maybe in the future I'll add a proper discovery mechanism.
-        // I would also like to integrate the Dht discovery mechanism.
-        // let discovery = ConcurrentDiscovery::from_services(vec![
-        //     Box::new(PkarrPublisher::n0_dns(secret_key.clone())),
-        //     Box::new(DnsDiscovery::n0_dns()),
-        //     Box::new(dht),
-        //     Box::new(LocalSwarmDiscovery::new(secret_key.public())?),
-        // ]);
-        // let endpoint = Endpoint::builder()
-        //     .secret_key(secret_key)
-        //     .discovery(Box::new(discovery))
-        //     .bind()
-        //     .await?;
-        let endpoint = Endpoint::builder()
-            .secret_key(secret_key)
-            .discovery_local_network()
-            .bind()
-            .await?;
-        let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
-        let router = Router::builder(endpoint.clone())
-            .accept(iroh_gossip::ALPN, gossip.clone())
-            .spawn()
-            .await?;
+        let (endpoint, gossip, router) = User::spawn_endpoint(secret_key, &relay_mode, &discovery).await?;
         let iroh_data = IrohData {
             endpoint,
             gossip,
             router,
             topic_id,
             relay_url,
+            relay_mode,
+            discovery,
         };
         let data = UserData {
             name: name.to_string(),
         };
-        Ok(User::Data {
-            iroh_data,
-            data,
-            debug: false,
-        })
+        Ok(User::Data { iroh_data: Box::new(iroh_data), data })
     }
 
     pub async fn recreate(self) -> Result<Self> {
         match self {
             IrohInstance::Empty => Ok(self),
-            IrohInstance::Data {
-                iroh_data,
-                data,
-                debug,
-            } => {
+            IrohInstance::Data { iroh_data, data } => {
                 let secret_key = iroh_data.endpoint.secret_key().clone();
                 let topic_id = iroh_data.topic_id;
                 let relay_url = iroh_data.relay_url;
                 let name = &data.name;
-                let mut new_user = User::create(secret_key, topic_id, relay_url, name).await?;
-                new_user.set_debug(debug)?;
-                Ok(new_user)
+                User::create(secret_key, topic_id, relay_url, name, iroh_data.relay_mode, iroh_data.discovery).await
             }
         }
     }
 
-    pub async fn random_with_topic(topic_id: TopicId) -> Result<Self> {
+    pub async fn random_with_topic(
+        topic_id: TopicId,
+        relay_mode: RelayMode,
+        discovery: DiscoveryConfig,
+    ) -> Result<Self> {
         let secret_key = SecretKey::generate(rand::rngs::OsRng);
-        let endpoint = Endpoint::builder().secret_key(secret_key).bind().await?;
-        let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
-        let router = Router::builder(endpoint.clone())
-            .accept(iroh_gossip::ALPN, gossip.clone())
-            .spawn()
-            .await?;
-        let relay_url = match endpoint.node_addr().await?.relay_url {
-            None => return Err(anyhow!("user::random::NoRelayUrlFound")),
-            Some(relay_url) => relay_url,
-        };
+        User::with_secret_key(secret_key, topic_id, relay_mode, discovery).await
+    }
+
+    // A user with a kept identity, like the one of 'ConnectConfig::identity_path'.
+    pub async fn with_secret_key(
+        secret_key: SecretKey,
+        topic_id: TopicId,
+        relay_mode: RelayMode,
+        discovery: DiscoveryConfig,
+    ) -> Result<Self> {
+        let (endpoint, gossip, router) = User::spawn_endpoint(secret_key, &relay_mode, &discovery).await?;
+        // Without relays (LAN-only mode) the peers are found through local network discovery.
+        let relay_url = endpoint.node_addr().await.map_err(Error::NodeAddr)?.relay_url;
+        if relay_url.is_none() && relay_mode != RelayMode::Disabled {
+            return Err(Error::NoRelayUrl("user::with_secret_key"));
+        }
         let iroh_data = IrohData {
             endpoint,
             gossip,
             router,
             topic_id,
             relay_url,
+            relay_mode,
+            discovery,
         };
         let name = "user_".to_string() + &random_string(7);
         let data = UserData { name };
-        Ok(User::Data {
-            iroh_data,
-            data,
-            debug: false,
-        })
+        Ok(User::Data { iroh_data: Box::new(iroh_data), data })
     }
 
-    pub async fn random() -> Result<Self> {
-        let secret_key = SecretKey::generate(rand::rngs::OsRng);
+    pub async fn random(relay_mode: RelayMode) -> Result<Self> {
         let topic_id = TopicId::from_bytes(rand::random());
-        let endpoint = Endpoint::builder().secret_key(secret_key).bind().await?;
+        User::random_with_topic(topic_id, relay_mode, DiscoveryConfig::default()).await
+    }
+
+    // The endpoint, with gossip and the slot probes served on it.
+    async fn spawn_endpoint(
+        secret_key: SecretKey,
+        relay_mode: &RelayMode,
+        discovery: &DiscoveryConfig,
+    ) -> Result<(Endpoint, Gossip, Router)> {
+        let builder = Endpoint::builder()
+            .secret_key(secret_key)
+            .relay_mode(relay_mode.clone());
+        let endpoint = discovery.apply(builder).bind().await.map_err(Error::Bind)?;
         let gossip = Gossip::builder().spawn(endpoint.clone()).await?;
         let router = Router::builder(endpoint.clone())
             .accept(iroh_gossip::ALPN, gossip.clone())
+            .accept(PROBE_ALPN, ProbeHandler::default())
             .spawn()
-            .await?;
-        let relay_url = match endpoint.node_addr().await?.relay_url {
-            None => return Err(anyhow!("user::random::NoRelayUrlFound")),
-            Some(relay_url) => relay_url,
-        };
-        let iroh_data = IrohData {
-            endpoint,
-            gossip,
-            router,
-            topic_id,
-            relay_url,
-        };
-        let name = "user_".to_string() + &random_string(7);
-        let data = UserData { name };
-        Ok(User::Data {
-            iroh_data,
-            data,
-            debug: false,
-        })
+            .await
+            .map_err(Error::Router)?;
+        Ok((endpoint, gossip, router))
     }
 }
 
@@ -145,23 +126,20 @@ impl User {
 impl User {
     pub async fn add_node_addresses(&self, node_addrs: &Vec<NodeAddr>) -> Result<()> {
         self.assert_correct_relay().await?;
-        let debug = self.debug();
         for node_addr in node_addrs {
-            if node_addr.relay_url.is_none() {
-                if debug {
-                    println!("> found empty node_addr");
-                }
+            if node_addr.is_empty() {
+                // Still reachable through discovery, if the node is on the local network.
+                tracing::trace!(node_id = %node_addr.node_id, "found empty node_addr");
                 continue;
             }
             if node_addr.relay_url != self.relay_url() {
-                if debug {
-                    println!(
-                        "> NodeArr has a different RelayUrl than this User:\n>\t{:?}\n>\t{:?}",
-                        node_addr.relay_url.clone().unwrap(),
-                        self.relay_url()
-                    );
-                }
-                continue;
+                // The endpoint dials through the relay of the node, not through ours.
+                tracing::trace!(
+                    node_id = %node_addr.node_id,
+                    node_relay = ?node_addr.relay_url,
+                    user_relay = ?self.relay_url(),
+                    "NodeAddr has a different RelayUrl than this User"
+                );
             }
             self.add_node_addr(node_addr.clone())?;
         }
@@ -171,56 +149,63 @@ impl User {
     pub async fn connect_to_servers(&self, server_addrs: Vec<NodeAddr>) -> Result<GossipFuture> {
         self.add_node_addresses(&server_addrs).await?;
         let iroh_data_clone = match self.iroh_data().clone() {
-            None => return Err(anyhow!("user::connect_to_servers::NoIrohDataFound")),
+            None => return Err(Error::EmptyInstance("user::connect_to_servers")),
             Some(iroh_data) => iroh_data,
         };
         let node_ids: Vec<NodeId> = server_addrs.iter().map(|addr| addr.node_id).collect();
-        let (debug, name) = (self.debug(), self.name().unwrap());
-        let user_handle: GossipFuture = tokio::spawn(async move {
-            if debug {
-                println!("> {name}: connecting to servers ...");
+        let span = tracing::debug_span!(
+            "connect_to_servers",
+            name = self.name().unwrap(),
+            node_id = %iroh_data_clone.endpoint.node_id(),
+            topic = %iroh_data_clone.topic_id,
+        );
+        let user_handle: GossipFuture = tokio::spawn(
+            async move {
+                tracing::debug!(n_servers = node_ids.len(), "connecting to servers ...");
+                let user_gtopic = iroh_data_clone
+                    .gossip
+                    .subscribe_and_join(iroh_data_clone.topic_id, node_ids)
+                    .await?;
+                tracing::debug!("connected!");
+                Ok(user_gtopic)
             }
-            let user_gtopic = iroh_data_clone
-                .gossip
-                .subscribe_and_join(iroh_data_clone.topic_id, node_ids)
-                .await?;
-            // let user_gtopic = user_clone.subscribe_and_join(node_ids).await?;
-            if debug {
-                println!("> {name}: connected!");
-            }
-            anyhow::Ok(user_gtopic)
-        });
+            .instrument(span),
+        );
         Ok(user_handle)
     }
 
-    pub async fn users_online(&self, relay_vec: &[&str], seed: &[u8; 32]) -> Result<Vec<NodeId>> {
+    // The online peers but the servers of the slots 'server_ids', derived from 'seed' with
+    // 'server_keys' like the connection did. A key is derived for every slot of the range.
+    pub async fn users_online(
+        &self,
+        seed: &[u8; 32],
+        server_keys: ServerKeys,
+        server_ids: Range<u64>,
+    ) -> Result<Vec<NodeId>> {
+        let topic_id = match self.topic_id() {
+            None => return Err(Error::EmptyInstance("user::users_online")),
+            Some(topic_id) => topic_id,
+        };
         let mut peer_ids: Vec<NodeId> = self.online_peers()?.keys().cloned().collect();
-        let server_ids: Vec<u64> = (0..100).collect();
-        let relay_vec: Vec<String> = relay_vec.iter().map(|s| s.to_string()).collect();
-        let only_server_ids: Vec<NodeId> = get_server_addresses(&server_ids, &relay_vec, seed)?
-            .iter()
-            .map(|addr| addr.node_id)
-            .collect();
-        if self.debug() {
-            println!("> 'all' peer_ids: {:?}", peer_ids);
+        tracing::trace!(?peer_ids, "'all' peer_ids");
+        if peer_ids.is_empty() {
+            return Ok(peer_ids);
         }
-
+        let slot_seed = server_keys.slot_seed(seed, &topic_id);
+        let only_server_ids: HashSet<NodeId> =
+            server_ids.map(|id| generate_server_secret_key(id, &slot_seed).public()).collect();
         peer_ids.retain(|peer| !only_server_ids.contains(peer));
-        if self.debug() {
-            println!("> 'non-server' peer_ids: {:?}", peer_ids);
-        }
-        if self.debug() {
-            println!("> user.node_id(): {:?}", self.node_id());
-        }
+        tracing::trace!(?peer_ids, node_id = ?self.node_id(), "'non-server' peer_ids");
         Ok(peer_ids)
     }
 
     pub async fn is_any_other_user_online(
         &self,
-        relay_vec: &[&str],
         seed: &[u8; 32],
+        server_keys: ServerKeys,
+        server_ids: Range<u64>,
     ) -> Result<bool> {
-        let users_vec = self.users_online(relay_vec, seed).await?;
+        let users_vec = self.users_online(seed, server_keys, server_ids).await?;
         Ok(!users_vec.is_empty())
     }
 }
@@ -236,7 +221,7 @@ impl User {
 
     pub fn set_name(&mut self, name: String) -> Result<&mut Self> {
         match self {
-            IrohInstance::Empty => return Err(anyhow!("user::set_name::UserIsEmpty")),
+            IrohInstance::Empty => return Err(Error::EmptyInstance("user::set_name")),
             IrohInstance::Data { data, .. } => {
                 data.name = name;
             }
@@ -252,21 +237,21 @@ mod tests {
     use std::{str::FromStr, time::Duration};
 
     use super::*;
+    use anyhow::Result;
     use crate::{
-        consts::{RELAY_VEC, SEED, TOPIC},
-        iroh::{ConnectOptions, Connection, Server, get_server_addresses},
+        consts::{SEED, TOPIC},
+        iroh::{Connection, Server, get_server_addresses},
+        tests::local_relay::LocalRelay,
     };
 
     #[tokio::test]
     // run test by using: 'cargo test iroh::user::tests::subscribe_to_node_addreses -- --exact --nocapture'
     async fn connect_and_boradcast() -> Result<()> {
-        tracing_subscriber::fmt::init();
-        let options = ConnectOptions {
-            debug: true,
-            ..Default::default()
-        };
+        let _ = tracing_subscriber::fmt::try_init();
+        let relay = LocalRelay::spawn().await?;
+        let options = relay.options();
         let topic_id = TopicId::from_str(TOPIC)?;
-        let connection = Connection::create_with_opts(topic_id, RELAY_VEC, &SEED, options).await?;
+        let connection = Connection::create_with_opts(topic_id, &[], &SEED, options).await?;
         let user = connection.user;
         let server_future = connection.server_future;
         let user_gtopic = connection.user_gossip_topic;
@@ -275,27 +260,28 @@ mod tests {
         sender.broadcast(Bytes::from("Hello!")).await?;
 
         tokio::time::sleep(Duration::from_millis(250)).await;
-        println!("> press Ctrl+C to exit.");
-        tokio::signal::ctrl_c().await?;
         println!("> online_peers:\n{:?}", user.online_peers()?.keys());
         println!("> closing server ...");
         server_future.close().await?;
         println!("> closing user ...");
         user.close().await?;
+        relay.shutdown().await?;
         Ok(())
     }
 
     #[tokio::test]
     // run test by using: 'cargo test iroh::user::tests::subscribe_to_node_addreses -- --exact --nocapture'
     async fn subscribe_to_node_addreses() -> Result<()> {
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
+        let relay = LocalRelay::spawn().await?;
         let topic_id = TopicId::from_str(TOPIC)?;
-        let relay_url = RelayUrl::from_str(RELAY_VEC[0])?;
+        let relay_url = Some(relay.relay_url());
 
         // Server side
         println!("> creating server ...");
         let id = 0;
-        let server = Server::create(id, topic_id, relay_url, &SEED).await?;
+        let server =
+            Server::create(id, topic_id, relay_url, &SEED, ServerKeys::default(), DiscoveryConfig::default()).await?;
         let mut server_gtopic = server.subscribe(vec![])?;
         tokio::spawn(async move { server_gtopic.joined().await });
         println!("> server is waiting for user to find it...");
@@ -305,11 +291,10 @@ mod tests {
 
         // User side
         println!("> creating user ...");
-        let mut user = User::random_with_topic(topic_id).await?;
-        user.set_debug(true)?;
+        let user = User::random_with_topic(topic_id, relay.options().relay_config.relay_mode(&[])?, DiscoveryConfig::default()).await?;
         let id_vec: Vec<u64> = (0..10).collect();
-        let relay_vec: Vec<String> = RELAY_VEC.iter().map(|s| s.to_string()).collect();
-        let server_addrs = get_server_addresses(&id_vec, &relay_vec, &SEED)?;
+        let relay_vec: Vec<String> = vec![relay.relay_url().to_string()];
+        let server_addrs = get_server_addresses(&id_vec, &relay_vec, &SEED, &topic_id, ServerKeys::default())?;
         // println!("> server_addrs:\n{:#?}", server_addrs);
         user.add_node_addresses(&server_addrs).await?;
         let node_ids: Vec<NodeId> = server_addrs.iter().map(|addr| addr.node_id).collect();
@@ -323,6 +308,7 @@ mod tests {
         server.close().await?;
         println!("> closing user ...");
         user.close().await?;
+        relay.shutdown().await?;
         Ok(())
     }
 
@@ -330,14 +316,16 @@ mod tests {
     // run test by using: 'cargo test iroh::user::tests::complex_example -- --exact --nocapture'
     async fn complex_example() -> Result<()> {
         // This shows a really simple implemntation of inner mecchanics of 'connect()'
-        tracing_subscriber::fmt::init();
+        let _ = tracing_subscriber::fmt::try_init();
+        let relay = LocalRelay::spawn().await?;
         let topic_id = TopicId::from_str(TOPIC)?;
-        let relay_url = RelayUrl::from_str(RELAY_VEC[0])?;
+        let relay_url = Some(relay.relay_url());
 
         // Server side
         println!("> creating server ...");
         let id = 0;
-        let server = Server::create(id, topic_id, relay_url, &SEED).await?;
+        let server =
+            Server::create(id, topic_id, relay_url, &SEED, ServerKeys::default(), DiscoveryConfig::default()).await?;
         let mut server_gtopic = server.subscribe(vec![])?;
         tokio::spawn(async move { server_gtopic.joined().await });
         println!("> server is waiting for user to find it...");
@@ -346,11 +334,10 @@ mod tests {
 
         // User side
         println!("> creating user ...");
-        let mut user = User::random_with_topic(topic_id).await?;
-        user.set_debug(true)?;
+        let user = User::random_with_topic(topic_id, relay.options().relay_config.relay_mode(&[])?, DiscoveryConfig::default()).await?;
         let id_vec: Vec<u64> = vec![id];
-        let relay_vec: Vec<String> = RELAY_VEC.iter().map(|s| s.to_string()).collect();
-        let server_addrs = get_server_addresses(&id_vec, &relay_vec, &SEED)?;
+        let relay_vec: Vec<String> = vec![relay.relay_url().to_string()];
+        let server_addrs = get_server_addresses(&id_vec, &relay_vec, &SEED, &topic_id, ServerKeys::default())?;
         let user_handle = user.connect_to_servers(server_addrs).await?;
         let user_gtopic = user_handle.await??;
         let (sender, _receiver) = user_gtopic.split();
@@ -363,6 +350,7 @@ mod tests {
         server.close().await?;
         println!("> closing user ...");
         user.close().await?;
+        relay.shutdown().await?;
         Ok(())
     }
 }
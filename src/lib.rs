@@ -1,3 +1,4 @@
+pub mod error;
 pub mod examples;
 pub mod tests;
 pub mod iroh;
@@ -8,3 +9,5 @@ pub mod meta;
 pub mod process;
 pub mod thread;
 pub mod vector;
+
+pub use error::{Error, Result};
@@ -9,38 +9,32 @@ use std::{
 
 use anyhow::Result;
 use iroh_gossip::{
-    net::{Event, GossipEvent, GossipReceiver, GossipTopic},
+    net::GossipTopic,
     proto::TopicId,
 };
 use lele::{
     consts::{RELAY_VEC, SEED, TOPIC},
     iroh::{
-        gossip::{Message, Sender, SignedMessage}, ConnectOptions, Connection, ServerFuture, User
+        gossip::{Message, Receiver, Sender}, ConnectOptions, Connection, ServerFuture, User
     },
 };
-use n0_future::TryStreamExt;
-
-const DEBUG: bool = false;
 
 #[tokio::main]
 // run test by using: 'cargo test examples::start_from_here::example -- --exact --nocapture'
 async fn main() -> Result<()> {
+    // Uncomment to see the tracing events emitted by lele.
     // tracing_subscriber::fmt::init();
     let start = Instant::now();
 
-    let options = ConnectOptions {
-        debug: DEBUG,
-        ..Default::default()
-    };
+    let options = ConnectOptions::default();
     let topic_id = TopicId::from_str(TOPIC)?;
     let connection = Connection::create_with_opts(topic_id, RELAY_VEC, &SEED, options).await?;
-    let user: User = connection.user;
-    let server_future: ServerFuture = connection.server_future;
-    let user_gtopic: GossipTopic = connection.user_gossip_topic;
+    let (user, server_future, user_gtopic): (User, ServerFuture, GossipTopic) = connection.into_parts();
 
     let (gossip_sender, receiver) = user_gtopic.split();
     let sender = Sender::create(&user, gossip_sender)?;
     let sender_clone = sender.clone();
+    let receiver = Receiver::create(receiver);
     tokio::spawn(async move { user_loop(sender_clone, receiver).await });
 
     /* Do somenthing with sender, like: */
@@ -52,35 +46,44 @@ async fn main() -> Result<()> {
     println!("> press Ctrl+C to exit.");
     tokio::signal::ctrl_c().await?;
     println!("> online_peers:\n{:?}", user.online_peers()?.keys());
-    println!("> closing server ...");
-    server_future.close().await?;
-    println!("> closing user ...");
-    user.close().await?;
+    println!("> closing server and user ...");
+    let report = server_future.shutdown(user, Duration::from_secs(5)).await;
+    println!("> shutdown report:\n{report:?}");
     Ok(())
 }
 
 // This is important, it defines how your app respond to each received Message.
 // To add new messages change the message.rs file.
-pub async fn user_loop(sender: Sender, mut receiver: GossipReceiver) -> Result<()> {
-    while let Some(event) = receiver.try_next().await? {
-        if let Event::Gossip(GossipEvent::Received(msg)) = event {
-            let (from, message) = SignedMessage::verify_and_decode(&msg.content)?;
-            match message {
-                Message::AboutMe { username } => {
-                    let msg = format!("hello {}!", &username);
-                    sender.broadcast(&Message::text(&msg)).await?;
-                }
-                Message::SimpleText { text } => {
-                    println!("> {}: {}", from.fmt_short(), text);
-                }
-                Message::RequestImg { image_name } => {
-                    println!(
-                        "> {} rquested image: {}",
-                        from.fmt_short(),
-                        image_name
-                    );
-                    /* Here you could use iroh-blobs to send the requested image if you have it */
-                }
+// 'Receiver' already verified the message, resolved rotated keys and applied moderation.
+pub async fn user_loop(sender: Sender, mut receiver: Receiver) -> Result<()> {
+    while let Some((from, message)) = receiver.next().await? {
+        match message {
+            Message::AboutMe { username } => {
+                let msg = format!("hello {}!", &username);
+                sender.broadcast(&Message::text(&msg)).await?;
+            }
+            Message::SimpleText { text } => {
+                println!("> {}: {}", from.fmt_short(), text);
+            }
+            Message::RequestImg { image_name } => {
+                println!(
+                    "> {} rquested image: {}",
+                    from.fmt_short(),
+                    image_name
+                );
+                /* Here you could use iroh-blobs to send the requested image if you have it */
+            }
+            Message::KeyRotation { .. } => {
+                println!("> {} rotated its key", from.fmt_short());
+            }
+            Message::Kick { target } | Message::Ban { target } | Message::Mute { target } => {
+                println!("> admin {} moderated {}", from.fmt_short(), target.fmt_short());
+            }
+            Message::SlotClaim { .. }
+            | Message::SlotRelease { .. }
+            | Message::SlotHeld { .. }
+            | Message::PeerExchange { .. } => {
+                /* Handled by lele, never received here */
             }
         }
     }
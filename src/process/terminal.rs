@@ -2,7 +2,7 @@ use crate::{
     process::{ShellType, TerminalOutput, is_powershell_installed},
     thread::timeout_wrapper,
 };
-use anyhow::Result;
+use crate::Result;
 use regex::Regex;
 use std::{
     io::{BufRead, BufReader, Read, Write},
@@ -20,7 +20,6 @@ pub struct Terminal {
     fmt_function: fn(&[u8]) -> String,
     timeout_duration: Duration,
     n_command_to_read: u32,
-    debug: bool,
 }
 
 impl Default for Terminal {
@@ -62,7 +61,6 @@ impl Terminal {
             terminal.stop_function,
             terminal.fmt_function,
             Duration::from_millis(1000),
-            false,
         )
         .expect("This should not fail");
         terminal
@@ -84,6 +82,11 @@ impl Terminal {
         }
     }
 
+    // Only the program is logged, its arguments may hold secrets.
+    #[tracing::instrument(
+        skip(self, command),
+        fields(shell = ?self.shell_type, program = command.split_whitespace().next().unwrap_or_default())
+    )]
     pub fn cmd(&mut self, command: &str) -> &mut Self {
         write(&mut self.stdin, command);
         self.n_command_to_read += 1;
@@ -95,27 +98,26 @@ impl Terminal {
         self
     }
 
+    #[tracing::instrument(skip(self), fields(shell = ?self.shell_type))]
     pub fn read_all(&mut self) -> Result<TerminalOutput> {
         let mut output = TerminalOutput::new();
         if self.n_command_to_read == 0 {
             self.n_command_to_read = 1;
         };
-        if self.debug {
-            println!(">>> reading {} command/s", self.n_command_to_read)
-        }
+        tracing::debug!(n_command_to_read = self.n_command_to_read, "reading command/s");
         for _ in 0..self.n_command_to_read {
             output.push_str(&read_line_per_line_with_timeout(
                 self.stdout_reader.clone(),
                 self.stop_function,
                 self.fmt_function,
                 self.timeout_duration,
-                self.debug,
             )?);
         }
         self.n_command_to_read = 0;
         Ok(output)
     }
 
+    #[tracing::instrument(skip(self), fields(shell = ?self.shell_type))]
     pub fn read(&mut self) -> Result<TerminalOutput> {
         let mut output = TerminalOutput::new();
         output.push_str(&read_line_per_line_with_timeout(
@@ -123,7 +125,6 @@ impl Terminal {
             self.stop_function,
             self.fmt_function,
             self.timeout_duration,
-            self.debug,
         )?);
         if self.n_command_to_read > 0 {
             self.n_command_to_read -= 1;
@@ -131,6 +132,7 @@ impl Terminal {
         Ok(output)
     }
 
+    #[tracing::instrument(skip(self), fields(shell = ?self.shell_type))]
     pub fn read_byte_per_byte(&mut self) -> Result<TerminalOutput> {
         let mut output = TerminalOutput::new();
         output.push_str(&read_byte_per_byte_with_timeout(
@@ -138,7 +140,6 @@ impl Terminal {
             self.stop_function,
             self.fmt_function,
             self.timeout_duration,
-            self.debug,
         )?);
         if self.n_command_to_read > 0 {
             self.n_command_to_read -= 1;
@@ -160,11 +161,6 @@ impl Terminal {
         self
     }
 
-    pub fn set_debug(&mut self, debug: bool) -> &mut Self {
-        self.debug = debug;
-        self
-    }
-
     pub fn close(self) {
         drop(self)
     }
@@ -202,7 +198,6 @@ fn create_new_terminal(shell_type: ShellType) -> Terminal {
         fmt_function,
         timeout_duration: Duration::from_millis(1500),
         n_command_to_read: 0,
-        debug: false,
     }
 }
 
@@ -302,13 +297,12 @@ fn read_line_per_line_with_timeout(
     stop_function: fn(&[u8]) -> bool,
     fmt_function: fn(&[u8]) -> String,
     timeout_duration: Duration,
-    debug: bool,
 ) -> Result<String> {
     timeout_wrapper(
         move || {
             // Lock the Mutex to get mutable access to BufReader within the thread
             let mut stdout_reader_guard = stdout_reader.lock().unwrap();
-            read_line_per_line(&mut stdout_reader_guard, stop_function, fmt_function, debug) // Dereference MutexGuard for &mut BufReader
+            read_line_per_line(&mut stdout_reader_guard, stop_function, fmt_function) // Dereference MutexGuard for &mut BufReader
         },
         timeout_duration,
     )
@@ -319,13 +313,12 @@ fn read_byte_per_byte_with_timeout(
     stop_function: fn(&[u8]) -> bool,
     fmt_function: fn(&[u8]) -> String,
     timeout_duration: Duration,
-    debug: bool,
 ) -> Result<String> {
     timeout_wrapper(
         move || {
             // Lock the Mutex to get mutable access to BufReader within the thread
             let mut stdout_reader_guard = stdout_reader.lock().unwrap();
-            read_byte_per_byte(&mut stdout_reader_guard, stop_function, fmt_function, debug) // Dereference MutexGuard for &mut BufReader
+            read_byte_per_byte(&mut stdout_reader_guard, stop_function, fmt_function) // Dereference MutexGuard for &mut BufReader
         },
         timeout_duration,
     )
@@ -335,17 +328,14 @@ fn read_line_per_line(
     stdout_reader_buf: &mut BufReader<ChildStdout>,
     stop_function: fn(&[u8]) -> bool,
     fmt_function: fn(&[u8]) -> String,
-    debug: bool,
 ) -> String {
     let mut buffer: Vec<u8> = Vec::new();
     loop {
         stdout_reader_buf
             .read_until(b'\n', &mut buffer)
             .expect(">>> ERROR:\n\t>> Could not read from stdout_reader\n");
-        let output = String::from_utf8_lossy(&buffer).to_string(); // to remove
-        if debug {
-            println!("output:\n{:?}", output); // to remove
-        }
+        // The shell echoes the commands back, so the line itself is not logged.
+        tracing::trace!(bytes = buffer.len(), "read line");
         if stop_function(&buffer) {
             break;
         }
@@ -357,7 +347,6 @@ fn read_byte_per_byte(
     stdout_reader_buf: &mut BufReader<ChildStdout>,
     stop_function: fn(&[u8]) -> bool,
     fmt_function: fn(&[u8]) -> String,
-    debug: bool,
 ) -> String {
     let mut buffer: Vec<u8> = Vec::new();
     let mut single_byte_buf: [u8; 1] = [0_u8];
@@ -365,9 +354,7 @@ fn read_byte_per_byte(
         stdout_reader_buf
             .read_exact(&mut single_byte_buf)
             .expect(">>> ERROR:\n\t>> Could not read from stdout_reader\n");
-        if debug {
-            print!("{}", String::from_utf8_lossy(&single_byte_buf));
-        }
+        tracing::trace!("read byte");
         buffer.push(single_byte_buf[0]);
         if stop_function(&buffer) {
             break;
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use iroh_gossip::proto::TopicId;
+
+use super::{local_pkarr::LocalPkarrRelay, local_relay::LocalRelay};
+use crate::iroh::{DiscoveryConfig, PROBE_ALPN, Server, ServerKeys, User};
+
+#[tokio::test]
+// run test by using: 'cargo test tests::discovery::server_found_through_pkarr -- --exact --nocapture'
+async fn server_found_through_pkarr() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let pkarr = LocalPkarrRelay::spawn().await?;
+    let discovery = DiscoveryConfig::PkarrRelay(pkarr.url());
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+
+    let relay_url = Some(relay.relay_url());
+    let server = Server::create(0, topic_id, relay_url, &seed, ServerKeys::default(), discovery.clone()).await?;
+    // The server publishes its relay as soon as it is connected to it.
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while pkarr.n_packets() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await?;
+
+    let relay_mode = relay.options().relay_config.relay_mode(&[])?;
+    let user = User::random_with_topic(topic_id, relay_mode, discovery).await?;
+    let endpoint = user.endpoint().unwrap();
+    // Only the node id is given, the address comes from the pkarr relay.
+    let server_node_id = server.node_id().unwrap();
+    let connection = tokio::time::timeout(Duration::from_secs(10), endpoint.connect(server_node_id, PROBE_ALPN)).await??;
+    connection.close(0u32.into(), b"found");
+    assert!(pkarr.n_resolved() > 0);
+
+    user.close().await?;
+    server.close().await?;
+    relay.shutdown().await?;
+    Ok(())
+}
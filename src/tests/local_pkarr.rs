@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow};
+use n0_future::task::AbortOnDropHandle;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+
+#[derive(Debug, Default)]
+struct Store {
+    // The signed packets by the z-base-32 key of their node.
+    packets: HashMap<String, Vec<u8>>,
+    // How many GET requests found a packet.
+    resolved: usize,
+}
+
+// A pkarr relay running in-process on localhost, so the discovery tests need no internet.
+// Like the real ones it keeps the packets PUT to '/pkarr/<key>' and serves them on GET,
+// the packets are not verified: the endpoints verify the signatures when resolving.
+#[derive(Debug)]
+pub(crate) struct LocalPkarrRelay {
+    url: Url,
+    store: Arc<Mutex<Store>>,
+    _task: AbortOnDropHandle<()>,
+}
+
+impl LocalPkarrRelay {
+    pub(crate) async fn spawn() -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let url = Url::parse(&format!("http://{}/pkarr", listener.local_addr()?))?;
+        let store = Arc::new(Mutex::new(Store::default()));
+        let store_clone = store.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let store = store_clone.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, store).await {
+                        tracing::debug!("local pkarr relay: {e}");
+                    }
+                });
+            }
+        });
+        tracing::debug!(%url, "local pkarr relay running");
+        Ok(LocalPkarrRelay {
+            url,
+            store,
+            _task: AbortOnDropHandle::new(task),
+        })
+    }
+
+    pub(crate) fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub(crate) fn n_packets(&self) -> usize {
+        self.store.lock().unwrap().packets.len()
+    }
+
+    pub(crate) fn n_resolved(&self) -> usize {
+        self.store.lock().unwrap().resolved
+    }
+}
+
+// HTTP/1.1 with keep-alive, only what the pkarr clients of iroh send.
+async fn serve(stream: TcpStream, store: Arc<Mutex<Store>>) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await? == 0 {
+            return Ok(());
+        }
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse()?;
+            }
+        }
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await?;
+
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method, path),
+            _ => return Err(anyhow!("bad request line '{request_line}'")),
+        };
+        let key = path.trim_start_matches("/pkarr/").to_string();
+        let (status, body) = match method {
+            "PUT" => {
+                store.lock().unwrap().packets.insert(key, body);
+                ("200 OK", Vec::new())
+            }
+            "GET" => {
+                let mut store = store.lock().unwrap();
+                match store.packets.get(&key).cloned() {
+                    None => ("404 Not Found", Vec::new()),
+                    Some(packet) => {
+                        store.resolved += 1;
+                        ("200 OK", packet)
+                    }
+                }
+            }
+            _ => ("405 Method Not Allowed", Vec::new()),
+        };
+        let head = format!("HTTP/1.1 {status}\r\ncontent-length: {}\r\n\r\n", body.len());
+        stream.get_mut().write_all(head.as_bytes()).await?;
+        stream.get_mut().write_all(&body).await?;
+    }
+}
@@ -0,0 +1,105 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use anyhow::{Result, anyhow, ensure};
+use iroh::{RelayMap, RelayNode, RelayUrl};
+use iroh_gossip::proto::TopicId;
+use iroh_relay::server::{AccessConfig, Server as RelayServer, ServerConfig, StunConfig};
+
+use crate::iroh::{ConnectOptions, Connection, RelayConfig, ServerFuture, User};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// An iroh relay running in-process on localhost, so the networking tests need no internet.
+// It serves plain http, the endpoints connect to it without any certificate.
+#[derive(Debug)]
+pub(crate) struct LocalRelay {
+    server: RelayServer,
+    relay_url: RelayUrl,
+    relay_map: RelayMap,
+}
+
+impl LocalRelay {
+    pub(crate) async fn spawn() -> Result<Self> {
+        let config: ServerConfig<(), ()> = ServerConfig {
+            relay: Some(iroh_relay::server::RelayConfig {
+                http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+            }),
+            stun: Some(StunConfig {
+                bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+            }),
+            quic: None,
+            metrics_addr: None,
+        };
+        let server = RelayServer::spawn(config).await?;
+        let relay_url = match server.http_url() {
+            None => return Err(anyhow!("local_relay::spawn::NoHttpUrlFound")),
+            Some(relay_url) => relay_url,
+        };
+        let stun_port = match server.stun_addr() {
+            None => return Err(anyhow!("local_relay::spawn::NoStunAddrFound")),
+            Some(stun_addr) => stun_addr.port(),
+        };
+        let relay_map = RelayMap::from_nodes([RelayNode {
+            url: relay_url.clone(),
+            stun_only: false,
+            stun_port,
+            quic: None,
+        }])?;
+        tracing::debug!(%relay_url, "local relay running");
+        Ok(LocalRelay {
+            server,
+            relay_url,
+            relay_map,
+        })
+    }
+
+    pub(crate) fn relay_url(&self) -> RelayUrl {
+        self.relay_url.clone()
+    }
+
+    // Options for fast tests: few server slots, short searches.
+    pub(crate) fn options(&self) -> ConnectOptions {
+        ConnectOptions {
+            search_duration: Duration::from_secs(2),
+            n_server_to_search: 5,
+            relay_config: RelayConfig::Custom(self.relay_map.clone()),
+            ..ConnectOptions::default()
+        }
+    }
+
+    // Connects 'n' users one after the other, every user after the first should find the swarm.
+    pub(crate) async fn spawn_users(&self, n: usize, topic_id: TopicId, seed: &[u8; 32]) -> Result<Vec<Connection>> {
+        let mut connections = Vec::with_capacity(n);
+        for _ in 0..n {
+            let connection = Connection::create_with_opts(topic_id, &[], seed, self.options()).await?;
+            connections.push(connection);
+        }
+        Ok(connections)
+    }
+
+    pub(crate) async fn shutdown(self) -> Result<()> {
+        self.server.shutdown().await
+    }
+}
+
+// Every connection must shut down cleanly.
+pub(crate) async fn shutdown_all(connections: Vec<Connection>) -> Result<()> {
+    for connection in connections {
+        let report = connection.shutdown(SHUTDOWN_TIMEOUT).await;
+        ensure!(report.is_clean(), "local_relay::shutdown_all::{report:?}");
+    }
+    Ok(())
+}
+
+// For tests that took the connections apart, see 'Connection::into_parts'.
+pub(crate) async fn close_users(users: Vec<(User, ServerFuture)>) -> Result<()> {
+    for (user, server_future) in users {
+        let report = server_future.shutdown(user, SHUTDOWN_TIMEOUT).await;
+        ensure!(report.is_clean(), "local_relay::close_users::{report:?}");
+    }
+    Ok(())
+}
@@ -1 +1,11 @@
 mod secret_key_from_seed;
+#[cfg(test)]
+mod discovery;
+#[cfg(test)]
+mod local_pkarr;
+#[cfg(test)]
+pub(crate) mod local_relay;
+#[cfg(test)]
+mod swarm;
+#[cfg(test)]
+mod simulation;
@@ -0,0 +1,308 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use futures::future::join_all;
+use iroh::{PublicKey, SecretKey};
+use iroh_gossip::proto::TopicId;
+use n0_future::task::AbortOnDropHandle;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::sync::mpsc;
+
+use super::local_relay::LocalRelay;
+use crate::iroh::{
+    ConnectOptions, Connection, ServerFuture, User,
+    gossip::{Message, Receiver, Sender},
+};
+
+// Dozens of endpoints closing on a loaded machine take longer than in the other tests.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the online nodes have to become one swarm once the schedule is over.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Step {
+    // The nodes bootstrap at the same time.
+    Join(Vec<usize>),
+    Leave(Vec<usize>),
+    Wait(Duration),
+}
+
+// When every node joins and leaves, the same seed always gives the same schedule.
+#[derive(Debug, Clone)]
+pub(crate) struct Schedule {
+    pub(crate) seed: u64,
+    pub(crate) n_nodes: usize,
+    pub(crate) steps: Vec<Step>,
+}
+
+impl Schedule {
+    pub(crate) fn new(seed: u64, n_nodes: usize, steps: Vec<Step>) -> Self {
+        Schedule { seed, n_nodes, steps }
+    }
+
+    // The nodes join in batches of up to 'max_batch', 'n_leaving' of them leave in between.
+    pub(crate) fn random(seed: u64, n_nodes: usize, max_batch: usize, n_leaving: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut steps = Vec::new();
+        let mut online: Vec<usize> = Vec::new();
+        let mut n_left = 0;
+        let mut next = 0;
+        while next < n_nodes {
+            let batch: Vec<usize> = (next..(next + rng.gen_range(1..=max_batch)).min(n_nodes)).collect();
+            next += batch.len();
+            online.extend(&batch);
+            steps.push(Step::Join(batch));
+            if n_left < n_leaving && online.len() > 1 && rng.gen_bool(0.5) {
+                steps.push(Step::Leave(vec![online.remove(rng.gen_range(0..online.len()))]));
+                n_left += 1;
+            }
+            steps.push(Step::Wait(Duration::from_millis(rng.gen_range(0..1000))));
+        }
+        // The last node always stays.
+        while n_left < n_leaving && online.len() > 1 {
+            steps.push(Step::Leave(vec![online.remove(rng.gen_range(0..online.len()))]));
+            n_left += 1;
+        }
+        Schedule { seed, n_nodes, steps }
+    }
+}
+
+struct Node {
+    user: User,
+    server_future: ServerFuture,
+    sender: Sender,
+    // The text messages received by the node, read in the background so the
+    // subscription never lags behind.
+    inbox: mpsc::UnboundedReceiver<(PublicKey, Message)>,
+    _reader: AbortOnDropHandle<()>,
+}
+
+// Many nodes of one topic in this process, bootstrapping against a local relay on a
+// join and leave schedule. Everything the run decides comes from the schedule seed: the
+// schedule, the node keys, the topic, the swarm seed and the random choices of every node,
+// see 'ConnectOptions::rng_seed'. The tests run on one thread and wait for the end state of
+// the seed, one swarm and one holder per slot, so packet timing only changes how long that
+// takes. Every error names the seed of the schedule.
+pub(crate) struct Simulation {
+    relay: LocalRelay,
+    seed: u64,
+    topic_id: TopicId,
+    swarm_seed: [u8; 32],
+    options: ConnectOptions,
+    secret_keys: Vec<SecretKey>,
+    rng_seeds: Vec<u64>,
+    nodes: BTreeMap<usize, Node>,
+}
+
+impl Simulation {
+    pub(crate) async fn spawn(schedule: &Schedule) -> Result<Self> {
+        let relay = LocalRelay::spawn().await?;
+        let mut rng = StdRng::seed_from_u64(schedule.seed);
+        let mut topic = [0u8; 32];
+        let mut swarm_seed = [0u8; 32];
+        rng.fill(&mut topic);
+        rng.fill(&mut swarm_seed);
+        let secret_keys = (0..schedule.n_nodes).map(|_| SecretKey::generate(&mut rng)).collect();
+        let rng_seeds = (0..schedule.n_nodes).map(|_| rng.r#gen()).collect();
+        // Separate swarms started by simultaneous joins are merged quickly. The bootstraps
+        // share one machine with the other tests, their slot search gets more time.
+        let options = ConnectOptions {
+            merge_interval: Some(Duration::from_secs(2)),
+            slot_search_deadline: Duration::from_secs(180),
+            ..relay.options()
+        };
+        Ok(Simulation {
+            relay,
+            seed: schedule.seed,
+            topic_id: TopicId::from_bytes(topic),
+            swarm_seed,
+            options,
+            secret_keys,
+            rng_seeds,
+            nodes: BTreeMap::new(),
+        })
+    }
+
+    pub(crate) async fn run(&mut self, schedule: &Schedule) -> Result<()> {
+        for step in &schedule.steps {
+            tracing::info!(seed = self.seed, ?step, "simulation step");
+            match step {
+                Step::Join(ids) => {
+                    let joined = join_all(ids.iter().map(|id| self.join(*id))).await;
+                    for (id, node) in ids.iter().zip(joined) {
+                        let node = node.with_context(|| format!("simulation seed {}: node {id} joining", self.seed))?;
+                        self.nodes.insert(*id, node);
+                    }
+                }
+                Step::Leave(ids) => {
+                    for id in ids {
+                        self.leave(*id).await?;
+                    }
+                }
+                Step::Wait(duration) => tokio::time::sleep(*duration).await,
+            }
+        }
+        Ok(())
+    }
+
+    async fn join(&self, id: usize) -> Result<Node> {
+        let relay_mode = self.options.relay_config.relay_mode(&[])?;
+        let secret_key = self.secret_keys[id].clone();
+        let user = User::with_secret_key(secret_key, self.topic_id, relay_mode, self.options.discovery.clone()).await?;
+        let options = ConnectOptions {
+            rng_seed: Some(self.rng_seeds[id]),
+            ..self.options.clone()
+        };
+        let connection = Connection::create_with_user(user, &[], &self.swarm_seed, options).await?;
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+        let sender = Sender::create(&user, gossip_sender)?;
+        let mut receiver = Receiver::create(gossip_receiver);
+        let (inbox_tx, inbox) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(async move {
+            while let Ok(Some(received)) = receiver.next().await {
+                let _ = inbox_tx.send(received);
+            }
+        });
+        Ok(Node {
+            user,
+            server_future,
+            sender,
+            inbox,
+            _reader: AbortOnDropHandle::new(reader),
+        })
+    }
+
+    async fn leave(&mut self, id: usize) -> Result<()> {
+        let Some(node) = self.nodes.remove(&id) else {
+            return Err(anyhow!("simulation seed {}: node {id} is not online", self.seed));
+        };
+        let Node { user, server_future, .. } = node;
+        let report = server_future.shutdown(user, SHUTDOWN_TIMEOUT).await;
+        ensure!(report.is_clean(), "simulation seed {}: node {id} left with {report:?}", self.seed);
+        Ok(())
+    }
+
+    // Eventually no server slot is bound by the open servers of two nodes. Nodes that are
+    // alone at the same time may bind the same slot until their swarms are merged.
+    pub(crate) async fn assert_single_holders(&self) -> Result<()> {
+        let settled = tokio::time::timeout(SETTLE_TIMEOUT, async {
+            loop {
+                let double_holders = self.double_holders();
+                if double_holders.is_empty() {
+                    return;
+                }
+                tracing::debug!(?double_holders, "waiting for the double holders to resolve");
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        })
+        .await;
+        ensure!(
+            settled.is_ok(),
+            "simulation seed {}: slots held by several nodes {:?}",
+            self.seed,
+            self.double_holders()
+        );
+        Ok(())
+    }
+
+    fn double_holders(&self) -> BTreeMap<u64, Vec<usize>> {
+        let mut holders: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        for (id, node) in &self.nodes {
+            for server in node.server_future.servers.lock().unwrap().iter() {
+                let is_open = server.endpoint().is_some_and(|endpoint| !endpoint.is_closed());
+                if let Some(slot) = server.id()
+                    && is_open
+                {
+                    holders.entry(slot).or_default().push(*id);
+                }
+            }
+        }
+        holders.retain(|_, nodes| nodes.len() > 1);
+        holders
+    }
+
+    // A message broadcast by the first online node reaches every other one.
+    pub(crate) async fn assert_one_swarm(&mut self) -> Result<()> {
+        let Some((&from_id, from)) = self.nodes.iter().next() else {
+            return Ok(());
+        };
+        let (sender, author) = (from.sender.clone(), from.sender.public_key());
+        let message = Message::text(&format!("simulation {} from node {from_id}", self.seed));
+        let mut missing: HashSet<usize> = self.nodes.keys().copied().filter(|id| *id != from_id).collect();
+        let settled = tokio::time::timeout(SETTLE_TIMEOUT, async {
+            while !missing.is_empty() {
+                // Sent again until the swarms are merged.
+                sender.broadcast(&message).await?;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                for (id, node) in self.nodes.iter_mut() {
+                    while let Ok(received) = node.inbox.try_recv() {
+                        if received == (author, message.clone()) {
+                            missing.remove(id);
+                        }
+                    }
+                }
+            }
+            Ok::<(), crate::Error>(())
+        })
+        .await;
+        match settled {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(anyhow!(
+                "simulation seed {}: nodes {missing:?} are not in the swarm of node {from_id}",
+                self.seed
+            )),
+        }
+    }
+
+    pub(crate) async fn shutdown(mut self) -> Result<()> {
+        let ids: Vec<usize> = self.nodes.keys().copied().collect();
+        for id in ids {
+            self.leave(id).await?;
+        }
+        self.relay.shutdown().await
+    }
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::simulation::simultaneous_joins -- --exact --nocapture'
+async fn simultaneous_joins() -> Result<()> {
+    // Every node is alone when the search ends and picks a slot of its own.
+    let schedule = Schedule::new(1, 6, vec![Step::Join((0..6).collect())]);
+    let mut simulation = Simulation::spawn(&schedule).await?;
+    simulation.run(&schedule).await?;
+    simulation.assert_one_swarm().await?;
+    simulation.assert_single_holders().await?;
+    simulation.shutdown().await
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::simulation::joins_and_leaves -- --exact --nocapture'
+async fn joins_and_leaves() -> Result<()> {
+    let schedule = Schedule::random(7, 12, 3, 3);
+    let mut simulation = Simulation::spawn(&schedule).await?;
+    simulation.run(&schedule).await?;
+    simulation.assert_one_swarm().await?;
+    simulation.assert_single_holders().await?;
+    simulation.shutdown().await
+}
+
+#[test]
+// run test by using: 'cargo test tests::simulation::same_seed_same_schedule -- --exact --nocapture'
+fn same_seed_same_schedule() {
+    let schedule = Schedule::random(7, 12, 3, 3);
+    assert_eq!(schedule.steps, Schedule::random(7, 12, 3, 3).steps);
+    assert_ne!(schedule.steps, Schedule::random(8, 12, 3, 3).steps);
+    let joined: usize = schedule.steps.iter().map(|step| match step {
+        Step::Join(ids) => ids.len(),
+        _ => 0,
+    }).sum();
+    let left: usize = schedule.steps.iter().map(|step| match step {
+        Step::Leave(ids) => ids.len(),
+        _ => 0,
+    }).sum();
+    assert_eq!((joined, left), (12, 3));
+}
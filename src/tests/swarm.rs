@@ -0,0 +1,330 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use iroh_gossip::proto::TopicId;
+
+use super::local_relay::{LocalRelay, close_users, shutdown_all};
+use crate::iroh::{
+    Connection, ConnectionEvent, ConnectionEventReceiver, PeerCache, SupervisedConnection,
+    gossip::{Message, Receiver, Sender},
+};
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::bootstrap_and_broadcast -- --exact --nocapture'
+async fn bootstrap_and_broadcast() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let connections = relay.spawn_users(3, topic_id, &seed).await?;
+
+    let mut users = Vec::new();
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    for connection in connections {
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+        // Every user should already be in the swarm.
+        assert!(gossip_receiver.is_joined());
+        senders.push(Sender::create(&user, gossip_sender)?);
+        receivers.push(Receiver::create(gossip_receiver));
+        users.push((user, server_future));
+    }
+
+    let message = Message::text("hello from the last user");
+    senders[2].broadcast(&message).await?;
+    for receiver in receivers.iter_mut().take(2) {
+        let received = tokio::time::timeout(Duration::from_secs(10), receiver.next()).await??;
+        assert_eq!(received, Some((senders[2].public_key(), message.clone())));
+    }
+
+    drop((senders, receivers));
+    close_users(users).await?;
+    relay.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::partitions_merge -- --exact --nocapture'
+async fn partitions_merge() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    // Disjoint slot ranges: each user bootstraps alone and starts its own swarm.
+    let mut first_options = relay.options();
+    first_options.starting_server_id = 0;
+    first_options.n_server_to_search = 2;
+    let mut second_options = relay.options();
+    second_options.starting_server_id = 2;
+    second_options.n_server_to_search = 2;
+    second_options.merge_interval = Some(Duration::from_secs(1));
+    let mut second_events = second_options.subscribe_events();
+    let first = Connection::create_with_opts(topic_id, &[], &seed, first_options).await?;
+    let second = Connection::create_with_opts(topic_id, &[], &seed, second_options).await?;
+
+    let joined = tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(event) = second_events.recv().await {
+            if let ConnectionEvent::JoiningForeignPeer { server_id, .. } = event {
+                return server_id;
+            }
+        }
+        None
+    })
+    .await?;
+    assert!(matches!(joined, Some(0 | 1)));
+
+    let mut users = Vec::new();
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    for connection in [first, second] {
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+        senders.push(Sender::create(&user, gossip_sender)?);
+        receivers.push(Receiver::create(gossip_receiver));
+        users.push((user, server_future));
+    }
+    // The first swarm now reaches the second one.
+    let message = Message::text("hello from the first swarm");
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            senders[0].broadcast(&message).await?;
+            let next = tokio::time::timeout(Duration::from_secs(1), receivers[1].next()).await;
+            if let Ok(received) = next {
+                return received;
+            }
+        }
+    })
+    .await??;
+    assert_eq!(received, Some((senders[0].public_key(), message)));
+
+    drop((senders, receivers));
+    close_users(users).await?;
+    relay.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::partitions_merge_through_cached_peers -- --exact --nocapture'
+async fn partitions_merge_through_cached_peers() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let dir = std::env::temp_dir().join(format!("lele-merge-{}", rand::random::<u64>()));
+    // Disjoint slot ranges and no slot probes: only the peer cache links the swarms.
+    let mut first_options = relay.options();
+    first_options.starting_server_id = 0;
+    first_options.n_server_to_search = 2;
+    let mut second_options = relay.options();
+    second_options.starting_server_id = 2;
+    second_options.n_server_to_search = 2;
+    second_options.merge_interval = Some(Duration::from_secs(1));
+    second_options.merge_probe_slots = 0;
+    second_options.peer_cache = Some(dir.join("peers.toml"));
+    let mut second_events = second_options.subscribe_events();
+    let first = Connection::create_with_opts(topic_id, &[], &seed, first_options).await?;
+    let second = Connection::create_with_opts(topic_id, &[], &seed, second_options).await?;
+
+    // Like a peer met in an earlier run, or learnt through a peer exchange.
+    let first_addr = first.user.node_addr().await?.unwrap();
+    let first_node_id = first_addr.node_id;
+    let peer_cache = second.server_future.peer_cache.clone().unwrap();
+    peer_cache.lock().unwrap().record(first_addr, u64::MAX);
+    let joined = tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(event) = second_events.recv().await {
+            if let ConnectionEvent::JoiningForeignPeer { server_id, node_id } = event {
+                return Some((server_id, node_id));
+            }
+        }
+        None
+    })
+    .await?;
+    assert_eq!(joined, Some((None, first_node_id)));
+
+    let mut users = Vec::new();
+    let mut senders = Vec::new();
+    let mut receivers = Vec::new();
+    for connection in [first, second] {
+        let (user, server_future, user_gossip_topic) = connection.into_parts();
+        let (gossip_sender, gossip_receiver) = user_gossip_topic.split();
+        senders.push(Sender::create(&user, gossip_sender)?);
+        receivers.push(Receiver::create(gossip_receiver));
+        users.push((user, server_future));
+    }
+    let message = Message::text("hello from the first swarm");
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            senders[0].broadcast(&message).await?;
+            let next = tokio::time::timeout(Duration::from_secs(1), receivers[1].next()).await;
+            if let Ok(received) = next {
+                return received;
+            }
+        }
+    })
+    .await??;
+    assert_eq!(received, Some((senders[0].public_key(), message)));
+
+    drop((senders, receivers));
+    close_users(users).await?;
+    relay.shutdown().await?;
+    std::fs::remove_dir_all(dir).ok();
+    Ok(())
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::peers_are_cached -- --exact --nocapture'
+async fn peers_are_cached() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let dir = std::env::temp_dir().join(format!("lele-peers-{}", rand::random::<u64>()));
+    let path = dir.join("peers.toml");
+    let mut options = relay.options();
+    options.peer_cache = Some(path.clone());
+
+    let first = Connection::create_with_opts(topic_id, &[], &seed, options.clone()).await?;
+    let other = Connection::create_with_opts(topic_id, &[], &seed, relay.options()).await?;
+    let other_node_id = other.user.node_id().unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !first.user.online_peers().unwrap().contains_key(&other_node_id) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    // Saved on shutdown.
+    shutdown_all(vec![first]).await?;
+    let cached = PeerCache::load(&path, topic_id).node_addrs();
+    assert!(cached.iter().any(|node_addr| node_addr.node_id == other_node_id));
+
+    // The next connection searches the cached peers with the server slots.
+    let mut events = options.subscribe_events();
+    let again = Connection::create_with_opts(topic_id, &[], &seed, options).await?;
+    let mut n_known = None;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            ConnectionEvent::SearchingServers { n_known_addresses, .. } => n_known = Some(n_known_addresses),
+            ConnectionEvent::NoPeerFound => panic!("the cached peers should be found"),
+            _ => {}
+        }
+    }
+    assert!(n_known.is_some_and(|n_known| n_known >= 1));
+
+    shutdown_all(vec![again, other]).await?;
+    relay.shutdown().await?;
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::peers_are_exchanged -- --exact --nocapture'
+async fn peers_are_exchanged() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let mut options = relay.options();
+    options.pex_interval = Some(Duration::from_secs(1));
+    let first = Connection::create_with_opts(topic_id, &[], &seed, options.clone()).await?;
+    let mut events = options.subscribe_events();
+    let second = Connection::create_with_opts(topic_id, &[], &seed, options).await?;
+
+    let first_node_id = first.user.node_id().unwrap();
+    let n_peers = tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(event) = events.recv().await {
+            if let ConnectionEvent::PeersExchanged { from, n_peers } = event
+                && from == first_node_id
+            {
+                return n_peers;
+            }
+        }
+        0
+    })
+    .await?;
+    // At least the server of the first user, the second one is left out.
+    assert!(n_peers >= 1);
+
+    shutdown_all(vec![first, second]).await?;
+    relay.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::supervised_without_reader -- --exact --nocapture'
+async fn supervised_without_reader() -> Result<()> {
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let first = SupervisedConnection::create_with_opts(topic_id, &[], &seed, relay.options()).await?;
+    let second = SupervisedConnection::create_with_opts(topic_id, &[], &seed, relay.options()).await?;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while first.neighbours() == 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    // The first app never takes its Receiver: more messages than it holds are dropped,
+    // and the supervisor still stops.
+    let sender = second.sender();
+    for i in 0..1280 {
+        sender.broadcast(&Message::text(&format!("message {i}"))).await?;
+        // Paced, gossip drops what it cannot send right away.
+        if i % 16 == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    tokio::time::timeout(Duration::from_secs(20), first.close()).await??;
+    tokio::time::timeout(Duration::from_secs(20), second.close()).await??;
+    relay.shutdown().await?;
+    Ok(())
+}
+
+// The events of a bootstrap up to its server joining the swarm.
+async fn events_until_server_connected(events: &mut ConnectionEventReceiver) -> Result<Vec<ConnectionEvent>> {
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(event) = events.recv().await {
+            let connected = matches!(event, ConnectionEvent::ServerConnected { .. });
+            received.push(event);
+            if connected {
+                break;
+            }
+        }
+    })
+    .await?;
+    Ok(received)
+}
+
+#[tokio::test]
+// run test by using: 'cargo test tests::swarm::lifecycle_events -- --exact --nocapture'
+async fn lifecycle_events() -> Result<()> {
+    use ConnectionEvent::*;
+    let relay = LocalRelay::spawn().await?;
+    let topic_id = TopicId::from_bytes(rand::random());
+    let seed: [u8; 32] = rand::random();
+    let mut first_options = relay.options();
+    let mut first_events = first_options.subscribe_events();
+    let first = Connection::create_with_opts(topic_id, &[], &seed, first_options).await?;
+    let first_events = events_until_server_connected(&mut first_events).await?;
+    let mut second_options = relay.options();
+    let mut second_events = second_options.subscribe_events();
+    let second = Connection::create_with_opts(topic_id, &[], &seed, second_options).await?;
+    let second_events = events_until_server_connected(&mut second_events).await?;
+    // PeerFound only after the user joined, the second user becomes a server once it is connected.
+    assert!(
+        matches!(
+            first_events.as_slice(),
+            [SearchingServers { .. }, NoPeerFound, BecomingServer { .. }, UserConnected, ServerConnected { .. }]
+        ),
+        "{first_events:?}"
+    );
+    assert!(
+        matches!(
+            second_events.as_slice(),
+            [SearchingServers { .. }, PeerFound, UserConnected, BecomingServer { .. }, ServerConnected { .. }]
+        ),
+        "{second_events:?}"
+    );
+
+    shutdown_all(vec![second, first]).await?;
+    relay.shutdown().await?;
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use crate::thread::TimeoutError;
-use anyhow::Result;
+use crate::Result;
 use std::{
     thread,
     time::{Duration, Instant},